@@ -13,43 +13,155 @@ namespace OpenEXR
         RGBF32 = 3
     }
 
+    [StructLayout(LayoutKind.Sequential)]
+    struct ExrBounds
+    {
+        public int X;
+        public int Y;
+        public int Width;
+        public int Height;
+    }
+
+    enum ExrStatus
+    {
+        Ok = 0,
+        IoError = 1,
+        BufferTooSmall = 2,
+        Unsupported = 3,
+        InvalidArgument = 4,
+        DecodeError = 5,
+        Aborted = 6,
+        NotExr = 7,
+        PartialData = 8,
+        VerificationFailed = 9,
+        NoPreview = 10
+    }
+
+    public enum ExrEncoding
+    {
+        Uncompressed = 0,
+        RLE = 1,
+        ZIP1 = 2,
+        ZIPS = ZIP1,
+        ZIP16 = 3,
+        ZIP = ZIP16,
+        PIZ = 4,
+        DWAA = 5,
+        DWAB = 6,
+        PXR24 = 7,
+        B44 = 8,
+        B44A = 9,
+        Auto = 10,
+        Other = -1
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    struct ExrLoadOptions
+    {
+        [MarshalAs(UnmanagedType.U1)] public bool Unpremultiply;
+        [MarshalAs(UnmanagedType.U1)] public bool Sanitize;
+        [MarshalAs(UnmanagedType.U1)] public bool FlipVertical;
+        [MarshalAs(UnmanagedType.U1)] public bool ToSrgb;
+        [MarshalAs(UnmanagedType.U1)] public bool FromSrgb;
+        [MarshalAs(UnmanagedType.U1)] public bool MemoryMap;
+        [MarshalAs(UnmanagedType.U1)] public bool Pedantic;
+        [MarshalAs(UnmanagedType.U1)] public bool ForceExr;
+        public ExrPixelFormat RequestedFormat;
+        public uint ChannelMask;
+        [MarshalAs(UnmanagedType.U1)] public bool MeasureLuminance;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    struct ExrWriteOptions
+    {
+        public ExrPixelFormat OutputFormat;
+        public ExrEncoding Encoding;
+        public float DwaCompressionLevel;
+        public uint TileWidth;
+        public uint TileHeight;
+        public uint BlockMode;
+        public uint LineOrder;
+        [MarshalAs(UnmanagedType.LPUTF8Str)] public string LayerName;
+        [MarshalAs(UnmanagedType.LPUTF8Str)] public string Owner;
+        [MarshalAs(UnmanagedType.LPUTF8Str)] public string Comments;
+        [MarshalAs(UnmanagedType.LPUTF8Str)] public string CaptureDate;
+        public IntPtr Chromaticities;
+        public int DataWindowX;
+        public int DataWindowY;
+        public IntPtr DisplayWindow;
+        public IntPtr TimeCode;
+        public IntPtr DefaultPixel;
+        public float PixelAspectRatio;
+        public uint NumThreads;
+        [MarshalAs(UnmanagedType.U1)] public bool Premultiplied;
+        [MarshalAs(UnmanagedType.U1)] public bool ConvertAlpha;
+        [MarshalAs(UnmanagedType.U1)] public bool FlipVertical;
+        public uint RowStrideBytes;
+        public uint PreviewSize;
+    }
+
     public static class ExrLoader
     {
         #pragma warning disable CA5393
         [DefaultDllImportSearchPaths(DllImportSearchPath.AssemblyDirectory)]
 
         [DllImport("../native/VL.OpenEXR.Native.dll")]
-        static extern IntPtr load_from_path(string path, out int width, out int height, out ExrPixelFormat format);
+        static extern ExrStatus load_from_path(string path, string layerName, [In] ref ExrLoadOptions options, out int width, out int height, out int numChannels, out ExrPixelFormat format, IntPtr channelNames, IntPtr channelFormats, uint channelFormatsLen, IntPtr info, out IntPtr data);
+
+        [DllImport("../native/VL.OpenEXR.Native.dll")]
+        static extern void free_texture(IntPtr data, uint width, uint height, uint numChannels, ExrPixelFormat format);
 
-        public static Texture LoadFromPath(string path, GraphicsDevice device, CommandList commandList)
+        public static Texture LoadFromPath(string path, GraphicsDevice device, CommandList commandList, string layerName = null, bool unpremultiply = false, bool memoryMap = false, bool sanitize = false, bool flipVertical = false, bool toSrgb = false, bool pedantic = false, bool forceExr = false)
         {
             ExrPixelFormat exrFormat;
             PixelFormat format;
-            IntPtr ptr = load_from_path(path, out var width, out var height, out exrFormat);
+            var options = new ExrLoadOptions
+            {
+                Unpremultiply = unpremultiply,
+                Sanitize = sanitize,
+                FlipVertical = flipVertical,
+                ToSrgb = toSrgb,
+                MemoryMap = memoryMap,
+                Pedantic = pedantic,
+                ForceExr = forceExr,
+                RequestedFormat = ExrPixelFormat.Unknown
+            };
+            ExrStatus status = load_from_path(path, layerName, ref options, out var width, out var height, out var numChannels, out exrFormat, IntPtr.Zero, IntPtr.Zero, 0, IntPtr.Zero, out var ptr);
 
-            if(exrFormat == ExrPixelFormat.Unknown || ptr == IntPtr.Zero)
+            if(status != ExrStatus.Ok || ptr == IntPtr.Zero)
             {
                 format = PixelFormat.None;
                 return null;
             }
             
             int sizeInBytes = 0;
-            bool hasAlpha = true;
-            (format, sizeInBytes, hasAlpha) = exrFormat switch
+            (format, sizeInBytes) = (exrFormat, numChannels) switch
             {
-                ExrPixelFormat.F16 => (PixelFormat.R16G16B16A16_Float, 2, true),
-                ExrPixelFormat.F32 => (PixelFormat.R32G32B32A32_Float, 4, true),
-                ExrPixelFormat.U32 => (PixelFormat.R32G32B32A32_UInt , 4, true),
-                ExrPixelFormat.RGBF32 => (PixelFormat.R32G32B32_Float, 4, false),
-                _ => (PixelFormat.None, 0, false),
+                (ExrPixelFormat.F16, 4) => (PixelFormat.R16G16B16A16_Float, 2),
+                (ExrPixelFormat.F32, 4) => (PixelFormat.R32G32B32A32_Float, 4),
+                (ExrPixelFormat.U32, 4) => (PixelFormat.R32G32B32A32_UInt , 4),
+                (ExrPixelFormat.F16, 2) => (PixelFormat.R16G16_Float, 2),
+                (ExrPixelFormat.F32, 2) => (PixelFormat.R32G32_Float, 4),
+                (ExrPixelFormat.U32, 2) => (PixelFormat.R32G32_UInt , 4),
+                (ExrPixelFormat.F16, 1) => (PixelFormat.R16_Float, 2),
+                (ExrPixelFormat.F32, 1) => (PixelFormat.R32_Float, 4),
+                (ExrPixelFormat.U32, 1) => (PixelFormat.R32_UInt , 4),
+                (ExrPixelFormat.RGBF32, 3) => (PixelFormat.R32G32B32_Float, 4),
+                _ => (PixelFormat.None, 0),
             };
 
-            var dataPointer = new DataPointer(ptr, width * height * (hasAlpha?4:3) * sizeInBytes);
+            if(format == PixelFormat.None)
+            {
+                free_texture(ptr, (uint)width, (uint)height, (uint)numChannels, exrFormat);
+                return null;
+            }
+
+            var dataPointer = new DataPointer(ptr, width * height * numChannels * sizeInBytes);
 
             var texture = Texture.New2D(device, width, height, format);
             texture.SetData(commandList, dataPointer);
 
-            Marshal.FreeCoTaskMem(ptr);
+            free_texture(ptr, (uint)width, (uint)height, (uint)numChannels, exrFormat);
 
             return texture;
         }
@@ -61,24 +173,67 @@ namespace OpenEXR
         [DefaultDllImportSearchPaths(DllImportSearchPath.AssemblyDirectory)]
 
         [DllImport("../native/VL.OpenEXR.Native.dll")]
-        static extern void write_texture(string path, int width, int height, ExrPixelFormat format, IntPtr data);
+        static extern ExrStatus write_texture(string path, int width, int height, uint numChannels, string[] channelNames, ExrPixelFormat inputFormat, [In] ref ExrWriteOptions options, IntPtr data);
+
+        [DllImport("../native/VL.OpenEXR.Native.dll")]
+        static extern int get_last_error(byte[] buf, UIntPtr len);
+
+        static string GetLastError()
+        {
+            int length = get_last_error(null, UIntPtr.Zero);
+            if(length <= 0) return string.Empty;
+
+            var buffer = new byte[length + 1];
+            get_last_error(buffer, (UIntPtr)buffer.Length);
+            return System.Text.Encoding.UTF8.GetString(buffer, 0, length);
+        }
         
-        public static void WriteTexture(byte[] data, string path, int width, int height, PixelFormat format)
+        public static void WriteTexture(byte[] data, string path, int width, int height, PixelFormat format, ExrEncoding encoding = ExrEncoding.RLE, float dwaCompressionLevel = 45.0f, uint tileWidth = 0, uint tileHeight = 0, string[] channelNames = null, string layerName = null, uint blockMode = 0, uint lineOrder = 0, string owner = null, string comments = null, string captureDate = null, float pixelAspectRatio = 1.0f, uint numThreads = 0, bool premultiplied = true, bool convertAlpha = false, int dataWindowX = 0, int dataWindowY = 0, bool flipVertical = false, uint previewSize = 0)
         {
-            ExrPixelFormat exrFormat = format switch
+            (ExrPixelFormat exrFormat, uint numChannels) = format switch
             {
-                PixelFormat.R32G32B32A32_UInt  => ExrPixelFormat.U32,
-                PixelFormat.R16G16B16A16_Float => ExrPixelFormat.F16,
-                PixelFormat.R32G32B32A32_Float => ExrPixelFormat.F32,
-                _ => ExrPixelFormat.Unknown
+                PixelFormat.R32G32B32A32_UInt  => (ExrPixelFormat.U32, 4u),
+                PixelFormat.R16G16B16A16_Float => (ExrPixelFormat.F16, 4u),
+                PixelFormat.R32G32B32A32_Float => (ExrPixelFormat.F32, 4u),
+                PixelFormat.R32G32_UInt  => (ExrPixelFormat.U32, 2u),
+                PixelFormat.R16G16_Float => (ExrPixelFormat.F16, 2u),
+                PixelFormat.R32G32_Float => (ExrPixelFormat.F32, 2u),
+                PixelFormat.R32_UInt  => (ExrPixelFormat.U32, 1u),
+                PixelFormat.R16_Float => (ExrPixelFormat.F16, 1u),
+                PixelFormat.R32_Float => (ExrPixelFormat.F32, 1u),
+                _ => (ExrPixelFormat.Unknown, 0u)
             };
 
             if(exrFormat == ExrPixelFormat.Unknown) return;
+            if(channelNames != null && channelNames.Length != numChannels) return;
 
+            ExrStatus status = ExrStatus.Ok;
             GCHandle handle = GCHandle.Alloc(data, GCHandleType.Pinned);
             try
             {
-                write_texture(path, width, height, exrFormat, handle.AddrOfPinnedObject());
+                var options = new ExrWriteOptions
+                {
+                    OutputFormat = ExrPixelFormat.Unknown,
+                    Encoding = encoding,
+                    DwaCompressionLevel = dwaCompressionLevel,
+                    TileWidth = tileWidth,
+                    TileHeight = tileHeight,
+                    BlockMode = blockMode,
+                    LineOrder = lineOrder,
+                    LayerName = layerName,
+                    Owner = owner,
+                    Comments = comments,
+                    CaptureDate = captureDate,
+                    DataWindowX = dataWindowX,
+                    DataWindowY = dataWindowY,
+                    PixelAspectRatio = pixelAspectRatio,
+                    NumThreads = numThreads,
+                    Premultiplied = premultiplied,
+                    ConvertAlpha = convertAlpha,
+                    FlipVertical = flipVertical,
+                    PreviewSize = previewSize
+                };
+                status = write_texture(path, width, height, numChannels, channelNames, exrFormat, ref options, handle.AddrOfPinnedObject());
             }
             catch(Exception e)
             {
@@ -88,6 +243,11 @@ namespace OpenEXR
             {
                 handle.Free();
             }
+
+            if(status != ExrStatus.Ok)
+            {
+                throw new InvalidOperationException($"Writing {path} failed with {status}: {GetLastError()}");
+            }
         }
     }
 }
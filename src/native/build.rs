@@ -0,0 +1,15 @@
+use std::fs;
+
+// Makes the resolved version of exr available to `library_version`
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let version = lock.split("[[package]]")
+        .find(|package| package.lines().any(|line| line.trim() == "name = \"exr\""))
+        .and_then(|package| package.lines().find_map(|line| line.trim().strip_prefix("version = ")))
+        .map(|version| version.trim_matches('"').to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=EXR_VERSION={}", version);
+}
@@ -1,207 +1,5278 @@
 use std::fs::File;
-use std::io::BufReader;
-use std::os::raw::c_char;
-use std::mem;
-use std::ffi::CStr;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::os::raw::{c_char, c_void};
+use std::ffi::{CStr, CString};
+use std::str::Utf8Error;
 use std::path::Path;
-use std::slice::from_raw_parts;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::slice::{from_raw_parts, from_raw_parts_mut};
 
 use exr::prelude::*;
+use exr::block::samples::{FromNativeSample, IntoNativeSample};
+use exr::block::{BlockIndex, UncompressedBlock};
+use exr::block::chunk::Chunk;
+use exr::block::writer::{ChunksWriter, ParallelBlocksCompressor};
+use rayon::iter::{IntoParallelIterator, ParallelBridge, ParallelIterator};
+use rayon::slice::ParallelSlice;
+use exr::meta::attribute::{AttributeValue, ChannelList, Chromaticities, LevelMode, Preview, TimeCode};
+use exr::meta::Requirements;
+use exr::math::RoundingMode;
+use exr::image::write::layers::{LayersWriter, WritableLayers};
+use exr::meta::header::Header;
+use exr::meta::BlockDescription;
+use exr::io::{Data, PeekRead, Tracking};
+use half::slice::HalfFloatSliceExt;
+
+// Stores the error for `get_last_error` and returns early with its `ExrStatus`
+macro_rules! unwrap_or_return_err {
+    ($result:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(e) => {
+                set_last_error(&e);
+                return ExrStatus::from(&e);
+            }
+        }
+    };
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Returned by every fallible entry point, 0 always meaning success.
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrStatus {
+    Ok = 0,
+    IoError = 1,
+    BufferTooSmall = 2,
+    Unsupported = 3,
+    InvalidArgument = 4,
+    DecodeError = 5,
+    Aborted = 6,
+    NotExr = 7,
+    /// Returned by `load_partial` when some blocks could not be decoded, the pixels still having been returned.
+    PartialData = 8,
+    /// Returned by `write_texture_verified` when the written file does not read back as the pixels it was given.
+    VerificationFailed = 9,
+    /// Returned by `read_preview` for files that carry no preview image.
+    NoPreview = 10,
+}
+
+impl From<&Error> for ExrStatus {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::Aborted => ExrStatus::Aborted,
+            Error::NotSupported(_) => ExrStatus::Unsupported,
+            Error::Invalid(_) => ExrStatus::DecodeError,
+            Error::Io(_) => ExrStatus::IoError,
+        }
+    }
+}
+
+impl From<&std::io::Error> for ExrStatus {
+    fn from(_: &std::io::Error) -> Self {
+        ExrStatus::IoError
+    }
+}
+
+impl From<&image::ImageError> for ExrStatus {
+    fn from(error: &image::ImageError) -> Self {
+        match error {
+            image::ImageError::IoError(_) => ExrStatus::IoError,
+            _ => ExrStatus::Unsupported,
+        }
+    }
+}
+
+impl From<&Utf8Error> for ExrStatus {
+    fn from(_: &Utf8Error) -> Self {
+        ExrStatus::InvalidArgument
+    }
+}
+
+fn set_last_error(error: impl Display) {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = error.to_string());
+}
+
+/// Copies the message of the last error raised on the calling thread into `buf`, truncated to `len - 1`
+/// bytes and nul-terminated. Returns the full length of the message in bytes, so a caller can retry with
+/// a larger buffer, or 0 when no error was raised yet. `buf` may be null to only query the length.
+///
+/// # Safety
+/// `buf` must be null or valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn get_last_error(buf: *mut c_char, len: usize) -> i32 {
+    LAST_ERROR.with(|last_error| copy_to_buffer(&last_error.borrow(), buf, len))
+}
+
+/// Copies the version of this library and of the exr crate it was built with into `buf`, like `get_last_error`,
+/// e.g. "vl_openexr_native 0.1.0 (exr 1.74.2)". Returns the full length of the string in bytes.
+///
+/// # Safety
+/// `buf` must be null or valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn library_version(buf: *mut c_char, len: usize) -> i32 {
+    let version = format!("{} {} (exr {})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), env!("EXR_VERSION"));
+    copy_to_buffer(&version, buf, len)
+}
+
+unsafe fn copy_to_buffer(string: &str, buf: *mut c_char, len: usize) -> i32 {
+    if !buf.is_null() && len > 0 {
+        let copied = string.len().min(len - 1);
+        std::ptr::copy_nonoverlapping(string.as_ptr() as *const c_char, buf, copied);
+        *buf.add(copied) = 0;
+    }
+
+    string.len() as i32
+}
+
+/// The sample type of a pixel buffer. Samples are stored as they are in the file, without widening: `F16` buffers
+/// hold 2-byte `half::f16` values, `U32` 4-byte `u32` and `F32` and `RGBF32` 4-byte `f32`, so a buffer of
+/// `width * height * num_channels` samples takes that many times the sample size, as `free_texture` expects.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrPixelFormat {
+    Unknown = -1,
+    U32 = 0,
+    F16 = 1,
+    F32 = 2,
+    RGBF32 = 3,
+}
+
+impl ExrPixelFormat {
+    // The size of one sample, taken from the types the buffers are allocated and freed as
+    fn element_size(self) -> usize {
+        match self {
+            ExrPixelFormat::F16 => mem::size_of::<f16>(),
+            ExrPixelFormat::U32 => mem::size_of::<u32>(),
+            ExrPixelFormat::F32 | ExrPixelFormat::RGBF32 => mem::size_of::<f32>(),
+            ExrPixelFormat::Unknown => 0,
+        }
+    }
+}
+
+impl From<SampleType> for ExrPixelFormat {
+    fn from(sample_type: SampleType) -> Self {
+        match sample_type {
+            SampleType::U32 => ExrPixelFormat::U32,
+            SampleType::F16 => ExrPixelFormat::F16,
+            SampleType::F32 => ExrPixelFormat::F32,
+        }
+    }
+}
+
+/// A rectangle in pixel space, such as the display window of an image.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExrBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl From<IntegerBounds> for ExrBounds {
+    fn from(bounds: IntegerBounds) -> Self {
+        ExrBounds {
+            x: bounds.position.x(),
+            y: bounds.position.y(),
+            width: bounds.size.width() as i32,
+            height: bounds.size.height() as i32,
+        }
+    }
+}
+
+/// The CIE xy coordinates of the RGB primaries and the white point of an image.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExrChromaticities {
+    pub red_x: f32,
+    pub red_y: f32,
+    pub green_x: f32,
+    pub green_y: f32,
+    pub blue_x: f32,
+    pub blue_y: f32,
+    pub white_x: f32,
+    pub white_y: f32,
+}
+
+impl From<Chromaticities> for ExrChromaticities {
+    fn from(chromaticities: Chromaticities) -> Self {
+        ExrChromaticities {
+            red_x: chromaticities.red.x(),
+            red_y: chromaticities.red.y(),
+            green_x: chromaticities.green.x(),
+            green_y: chromaticities.green.y(),
+            blue_x: chromaticities.blue.x(),
+            blue_y: chromaticities.blue.y(),
+            white_x: chromaticities.white.x(),
+            white_y: chromaticities.white.y(),
+        }
+    }
+}
+
+impl From<ExrChromaticities> for Chromaticities {
+    fn from(chromaticities: ExrChromaticities) -> Self {
+        Chromaticities {
+            red: Vec2(chromaticities.red_x, chromaticities.red_y),
+            green: Vec2(chromaticities.green_x, chromaticities.green_y),
+            blue: Vec2(chromaticities.blue_x, chromaticities.blue_y),
+            white: Vec2(chromaticities.white_x, chromaticities.white_y),
+        }
+    }
+}
+
+/// A SMPTE time code. With `drop_frame`, frames are counted the NTSC way, where frames 0 and 1 are skipped at
+/// the start of every minute except for each tenth one. Other time codes count every frame.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExrTimeCode {
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    pub frame: u32,
+    pub drop_frame: bool,
+}
+
+impl ExrTimeCode {
+    fn is_valid(&self) -> bool {
+        let skipped = self.drop_frame && self.seconds == 0 && self.frame < 2 && !self.minutes.is_multiple_of(10);
+        self.hours < 24 && self.minutes < 60 && self.seconds < 60 && self.frame < 30 && !skipped
+    }
+}
+
+impl From<TimeCode> for ExrTimeCode {
+    fn from(time_code: TimeCode) -> Self {
+        ExrTimeCode {
+            hours: time_code.hours.into(),
+            minutes: time_code.minutes.into(),
+            seconds: time_code.seconds.into(),
+            frame: time_code.frame.into(),
+            drop_frame: time_code.drop_frame,
+        }
+    }
+}
+
+/// The range and average of the luminance of an image, as `load_from_path` measures it for auto exposure.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExrLuminanceStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+/// How `load_from_path` decodes an image, each field being described there. `default_load_options` returns the
+/// settings a null pointer stands for, which callers change the fields they need of.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ExrLoadOptions {
+    pub unpremultiply: bool,
+    pub sanitize: bool,
+    pub flip_vertical: bool,
+    pub to_srgb: bool,
+    pub from_srgb: bool,
+    pub memory_map: bool,
+    pub pedantic: bool,
+    pub force_exr: bool,
+    pub requested_format: ExrPixelFormat,
+    pub channel_mask: u32,
+    pub measure_luminance: bool,
+}
+
+impl Default for ExrLoadOptions {
+    fn default() -> Self {
+        ExrLoadOptions {
+            unpremultiply: false,
+            sanitize: false,
+            flip_vertical: false,
+            to_srgb: false,
+            from_srgb: false,
+            memory_map: false,
+            pedantic: false,
+            force_exr: false,
+            requested_format: ExrPixelFormat::Unknown,
+            channel_mask: 0,
+            measure_luminance: false,
+        }
+    }
+}
+
+/// What `load_from_path` reports about a decoded image besides its pixels, each field being described there.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExrLoadInfo {
+    pub has_alpha: bool,
+    pub data_window_x: i32,
+    pub data_window_y: i32,
+    pub display_window: ExrBounds,
+    pub has_chromaticities: bool,
+    pub chromaticities: ExrChromaticities,
+    pub pixel_aspect_ratio: f32,
+    pub is_uniform: bool,
+    pub encoding: ExrEncoding,
+    pub has_time_code: bool,
+    pub time_code: ExrTimeCode,
+    pub num_layers: i32,
+    pub luminance_stats: ExrLuminanceStats,
+}
+
+impl Default for ExrLoadInfo {
+    fn default() -> Self {
+        ExrLoadInfo {
+            has_alpha: false,
+            data_window_x: 0,
+            data_window_y: 0,
+            display_window: ExrBounds::default(),
+            has_chromaticities: false,
+            chromaticities: ExrChromaticities::default(),
+            pixel_aspect_ratio: 1.0,
+            is_uniform: false,
+            encoding: ExrEncoding::Other,
+            has_time_code: false,
+            time_code: ExrTimeCode::default(),
+            num_layers: 0,
+            luminance_stats: ExrLuminanceStats::default(),
+        }
+    }
+}
+
+// Only valid time codes may be converted, exr panics on values out of range when writing
+impl From<ExrTimeCode> for TimeCode {
+    fn from(time_code: ExrTimeCode) -> Self {
+        TimeCode {
+            hours: time_code.hours as u8,
+            minutes: time_code.minutes as u8,
+            seconds: time_code.seconds as u8,
+            frame: time_code.frame as u8,
+            drop_frame: time_code.drop_frame,
+            color_frame: false,
+            field_phase: false,
+            binary_group_flags: [false; 3],
+            binary_groups: [0; 8],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrEncoding {
+    Uncompressed = 0,
+    RLE = 1,
+    /// Deflate compressing each scan line on its own, called ZIPS by OpenEXR. Written as blocks of one scan line
+    /// unless tiles are requested.
+    ZIP1 = 2,
+    /// Deflate compressing blocks of 16 scan lines together, called ZIP by OpenEXR. Smaller than `ZIP1`, but a
+    /// reader has to decompress 16 lines to get at one.
+    ZIP16 = 3,
+    PIZ = 4,
+    DWAA = 5,
+    DWAB = 6,
+    /// Lossy for `F32` channels only, which are rounded to 24 bits by dropping the low 8 of their 23 mantissa bits.
+    /// `F16` and `U32` channels are stored losslessly.
+    PXR24 = 7,
+    /// Lossy for `F16` channels only, which are packed in blocks of 4x4 storing the largest value and the
+    /// differences to it in 6 bits each, so precision drops where a block spans a wide range. `F32` and `U32`
+    /// channels are not compressed at all but stored as they are, which keeps them exact at their full size.
+    B44 = 8,
+    /// Like `B44`, but blocks of a single value shrink to 3 bytes and come back exact, as those of an opaque alpha do.
+    B44A = 9,
+    /// Lets `write_texture` pick a lossless compression from the pixels: `RLE` for U32 data where most samples repeat
+    /// the one to their left, such as ID passes, `PIZ` for F16 data where most do not, such as grainy plates, and
+    /// `ZIP16` otherwise, which includes all F32 data. Channels whose samples all repeat, such as an opaque alpha, are
+    /// not considered. Writers that do not look at the pixels use `ZIP16`.
+    Auto = 10,
+    // Read only, for compressions without a variant of their own
+    Other = -1,
+}
+
+impl From<Compression> for ExrEncoding {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Uncompressed => ExrEncoding::Uncompressed,
+            Compression::RLE => ExrEncoding::RLE,
+            Compression::ZIP1 => ExrEncoding::ZIP1,
+            Compression::ZIP16 => ExrEncoding::ZIP16,
+            Compression::PIZ => ExrEncoding::PIZ,
+            Compression::DWAA(_) => ExrEncoding::DWAA,
+            Compression::DWAB(_) => ExrEncoding::DWAB,
+            Compression::PXR24 => ExrEncoding::PXR24,
+            Compression::B44 => ExrEncoding::B44,
+            Compression::B44A => ExrEncoding::B44A,
+            _ => ExrEncoding::Other,
+        }
+    }
+}
+
+/// Receives the finished fraction of a long operation, from 0.0 to 1.0.
+pub type ExrProgressCallback = extern "C" fn(f32);
+
+/// How `write_texture` stores an image, each field being described there. `default_write_options` returns the
+/// settings a null pointer stands for, which callers change the fields they need of.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ExrWriteOptions {
+    pub output_format: ExrPixelFormat,
+    pub encoding: ExrEncoding,
+    pub dwa_compression_level: f32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub block_mode: u32,
+    pub line_order: u32,
+    pub layer_name: *const c_char,
+    pub owner: *const c_char,
+    pub comments: *const c_char,
+    pub capture_date: *const c_char,
+    pub chromaticities: *const ExrChromaticities,
+    pub data_window_x: i32,
+    pub data_window_y: i32,
+    pub display_window: *const ExrBounds,
+    pub time_code: *const ExrTimeCode,
+    pub default_pixel: *const [f32; 4],
+    pub pixel_aspect_ratio: f32,
+    pub num_threads: u32,
+    pub premultiplied: bool,
+    pub convert_alpha: bool,
+    pub flip_vertical: bool,
+    pub row_stride_bytes: u32,
+    pub preview_size: u32,
+}
+
+impl Default for ExrWriteOptions {
+    fn default() -> Self {
+        ExrWriteOptions {
+            output_format: ExrPixelFormat::Unknown,
+            encoding: ExrEncoding::RLE,
+            dwa_compression_level: DEFAULT_DWA_COMPRESSION_LEVEL,
+            tile_width: 0,
+            tile_height: 0,
+            block_mode: BLOCK_MODE_AUTO,
+            line_order: LINE_ORDER_AUTO,
+            layer_name: std::ptr::null(),
+            owner: std::ptr::null(),
+            comments: std::ptr::null(),
+            capture_date: std::ptr::null(),
+            chromaticities: std::ptr::null(),
+            data_window_x: 0,
+            data_window_y: 0,
+            display_window: std::ptr::null(),
+            time_code: std::ptr::null(),
+            default_pixel: std::ptr::null(),
+            pixel_aspect_ratio: 1.0,
+            num_threads: 0,
+            premultiplied: true,
+            convert_alpha: false,
+            flip_vertical: false,
+            row_stride_bytes: 0,
+            preview_size: 0,
+        }
+    }
+}
+
+/// Returns the options `write_texture` uses for a null `options`: the samples stored as they are with `RLE`, the
+/// layout of the encoding, no attributes beyond the required ones, square pixels and premultiplied alpha.
+#[no_mangle]
+pub extern "C" fn default_write_options() -> ExrWriteOptions {
+    ExrWriteOptions::default()
+}
+
+/// Writes `width * height` pixels of `num_channels` interleaved samples to the EXR file at `path`, stored as `options`
+/// asks, null taking `default_write_options()`. The fields of `options` are described below along with the parameters.
+/// `width` and `height` must be at least 1, as EXR cannot store empty images, or `ExrStatus::InvalidArgument` is returned.
+/// `dwa_compression_level` is only used by the DWA encodings, 45.0 being the OpenEXR default.
+/// Higher values give smaller files at the cost of quality.
+/// `input_format` is the type of the samples in `data` and `output_format` the type they are stored as, `Unknown`
+/// storing them unchanged. F32 data can be narrowed to F16 and F16 data widened to F32, U32 data is only stored as U32.
+/// `data` interleaves the samples of `num_channels` channels per pixel. `channel_names` is either null or an array
+/// of `num_channels` names, defaulting to "Y" for one channel, "X"/"Y" for two, RGB for three and RGBA for four.
+/// A single U32 channel defaults to "id" instead, as object and material ID passes are stored, and may be named
+/// "ObjectID" or the like to match the ID workflow of a compositor. Its integers are kept exactly by every encoding.
+/// Other counts, such as a stack of AOVs, need their names. EXR stores channels sorted by name, which is the order
+/// `load_from_path` returns them in, except for RGB and RGBA.
+/// When both `tile_width` and `tile_height` are non-zero, the file is written with tiles of that size
+/// regardless of `encoding`, otherwise the encoding's default block layout is used.
+/// `block_mode` overrides that layout, 0 meaning auto, 1 scan lines and 2 tiles. Tiles are 64x64 unless the encoding
+/// or `tile_width` and `tile_height` specify otherwise, scan lines ignore the tile size.
+/// The tile size need not divide the image size, the tiles at the right and bottom edges are cut off at the image
+/// and hold the same pixels as the scan lines would.
+/// `line_order` overrides the order blocks are stored in, 0 meaning auto, 1 increasing, 2 decreasing and 3 unspecified.
+/// Scan line images cannot leave it unspecified and are written in increasing order instead.
+/// `owner`, `comments` and `capture_date` set the matching attributes unless null or empty, the capture date being
+/// formatted as "YYYY:MM:DD hh:mm:ss".
+/// `chromaticities` describes the primaries and white point of the RGB data, null leaving the attribute out.
+/// `data_window_x` and `data_window_y` place the first pixel of `data` within the display window, such as the
+/// corner of a crop, and are reported back by `load_from_path`.
+/// `display_window` is the frame the image is meant to be shown in, null making it the same as the data window of
+/// `width * height` pixels at that position. An overscan render passes a smaller frame inside the pixels, and the frame may
+/// also reach beyond them. Its width and height must be greater than zero.
+/// `time_code` is stored in the "timeCode" attribute editorial tools key frames by, null leaving it out.
+/// `default_pixel` replaces samples that would be stored as NaN or infinity, such as holes in a render, channel `i`
+/// of a pixel taking `default_pixel[i]` and channels past the fourth `default_pixel[3]`. Null stores them as they are.
+/// `pixel_aspect_ratio` is the width of a pixel divided by its height, such as 2.0 for anamorphic footage. It must be
+/// greater than zero, 1.0 meaning square pixels.
+/// `layer_name` may be null or empty to keep the default "first layer".
+/// `num_threads` limits how many threads compress blocks, 0 using one per core and 1 compressing on the calling thread.
+/// `premultiplied` tells whether the color channels are stored multiplied by alpha, which EXR readers assume by
+/// default. Straight alpha is marked with the "oiio:UnassociatedAlpha" attribute OpenImageIO based tools look for.
+/// The flag only describes the stored pixels. To premultiply straight `data` while writing, also set
+/// `convert_alpha`, which multiplies the channels named R, G and B by the one named A for F16 and F32 data.
+/// `flip_vertical` takes the first row of `data` as the bottom of the image, as OpenGL lays out textures, instead of
+/// the top. The data window is placed the same either way.
+/// `row_stride_bytes` is the distance from the start of one row of `data` to the next, for buffers whose rows are
+/// padded to an alignment such as GPU readbacks. It must be a multiple of the sample size and at least
+/// `width * num_channels` samples, 0 meaning tightly packed rows. The padding is never read.
+/// `preview_size` embeds a thumbnail of at most that many pixels along its longer edge in the "preview" attribute,
+/// which file browsers show and `read_preview` returns without decoding the image. It is downscaled by averaging,
+/// encoded with the sRGB curve and clamped to 8 bits like `write_png_preview` does at an exposure of 0, and never
+/// larger than the image. 0 writes no preview. It is made of the samples as stored, after `default_pixel` replaced
+/// the ones that are not finite. Images of more than four channels are previewed from the first three.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `options` null or valid, with the strings and values it points to
+/// null or valid as well, and `data` must point to `height` rows of `width * num_channels` samples of `input_format`,
+/// each row starting `row_stride_bytes` after the previous one.
+#[no_mangle]
+pub unsafe extern "C" fn write_texture(
+    path: *const c_char, width: i32, height: i32, num_channels: u32, channel_names: *const *const c_char,
+    input_format: ExrPixelFormat, options: *const ExrWriteOptions, data: *const c_void
+) -> ExrStatus {
+    write_texture_with_attrs(
+        path, width, height, num_channels, channel_names, input_format, options, None, std::ptr::null(), 0, std::ptr::null(),
+        std::ptr::null(), data
+    )
+}
+
+/// Writes like `write_texture`, additionally storing `num_attributes` custom string attributes on the layer.
+/// Attribute `i` is called `attribute_names[i]` and holds `attribute_values[i]`. Names should not clash with the
+/// standard attributes of the format, such as "owner", which have their own parameters.
+/// Unless null, `progress` is called as blocks are stored, from the calling thread, and last with exactly 1.0.
+/// Setting the flag `cancel` points to, e.g. from another thread, stops the write at the next block with
+/// `ExrStatus::Aborted` and removes the unfinished file. `cancel` may be null.
+///
+/// # Safety
+/// Same as `write_texture`, `cancel` must be null or valid until this returns, and `attribute_names` and
+/// `attribute_values` must either be null or hold `num_attributes` valid nul-terminated strings each.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_texture_with_attrs(
+    path: *const c_char, width: i32, height: i32, num_channels: u32, channel_names: *const *const c_char,
+    input_format: ExrPixelFormat, options: *const ExrWriteOptions, progress: Option<ExrProgressCallback>,
+    cancel: *const AtomicBool, num_attributes: u32, attribute_names: *const *const c_char, attribute_values: *const *const c_char,
+    data: *const c_void
+) -> ExrStatus {
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let ExrWriteOptions {
+        output_format, encoding, dwa_compression_level, tile_width, tile_height, block_mode, line_order, layer_name, owner,
+        comments, capture_date, chromaticities, data_window_x, data_window_y, display_window, time_code, default_pixel,
+        pixel_aspect_ratio, num_threads, premultiplied, convert_alpha, flip_vertical, row_stride_bytes, preview_size,
+    } = options.as_ref().copied().unwrap_or_default();
+
+    if width <= 0 || height <= 0 {
+        set_last_error(format!("invalid image size {}x{}", width, height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let (width, height, num_channels) = (width as usize, height as usize, num_channels as usize);
+
+    let row_len = width * num_channels;
+    let Some(row_stride) = row_stride_samples(row_len, input_format.element_size(), row_stride_bytes as usize) else {
+        set_last_error(format!("invalid row stride of {} bytes for {} {:?} samples", row_stride_bytes, row_len, input_format));
+        return ExrStatus::InvalidArgument;
+    };
+    let len = strided_len(row_len, row_stride, height);
+
+    if !pixel_aspect_ratio.is_finite() || pixel_aspect_ratio <= 0.0 {
+        set_last_error(format!("invalid pixel aspect ratio {}", pixel_aspect_ratio));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let data_window = IntegerBounds::new(Vec2(data_window_x, data_window_y), Vec2(width, height));
+    if data_window.validate(None).is_err() {
+        set_last_error(format!("invalid data window {}x{} at {}, {}", width, height, data_window_x, data_window_y));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let display_window = display_window.as_ref().map(|bounds| {
+        IntegerBounds::new(Vec2(bounds.x, bounds.y), Vec2(bounds.width.max(0) as usize, bounds.height.max(0) as usize))
+    });
+
+    if let Some(bounds) = display_window {
+        if bounds.size.area() == 0 || bounds.validate(None).is_err() {
+            set_last_error(format!("invalid display window {}x{} at {}, {}", bounds.size.width(), bounds.size.height(), bounds.position.x(), bounds.position.y()));
+            return ExrStatus::InvalidArgument;
+        }
+    }
+
+    let time_code = time_code.as_ref().copied();
+    if let Some(time_code) = time_code.filter(|time_code| !time_code.is_valid()) {
+        set_last_error(format!("invalid time code {:?}", time_code));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let channel_names = read_str_array(channel_names, num_channels).unwrap_or_else(|| default_channel_names(num_channels, input_format));
+    if channel_names.len() != num_channels {
+        set_last_error(format!("{} channels need channel names", num_channels));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let options = WriteOptions {
+        channel_names,
+        encoding,
+        dwa_compression_level,
+        tile_size: if tile_width > 0 && tile_height > 0 { Some(Vec2(tile_width as usize, tile_height as usize)) } else { None },
+        block_mode,
+        line_order,
+        layer_name: read_optional_str(layer_name).unwrap_or_else(|| DEFAULT_LAYER_NAME.to_string()),
+        owner: read_optional_str(owner),
+        comments: read_optional_str(comments),
+        capture_date: read_optional_str(capture_date),
+        chromaticities: chromaticities.as_ref().map(|chromaticities| (*chromaticities).into()),
+        data_window_position: data_window.position,
+        display_window,
+        time_code: time_code.map(TimeCode::from),
+        default_pixel: default_pixel.as_ref().copied(),
+        pixel_aspect_ratio,
+        row_stride,
+        flip_vertical,
+        num_threads,
+        progress,
+        cancel: cancel.as_ref(),
+        premultiplied,
+        attributes: read_str_array(attribute_names, num_attributes as usize).unwrap_or_default().into_iter()
+            .zip(read_str_array(attribute_values, num_attributes as usize).unwrap_or_default())
+            .collect(),
+        source_attributes: None,
+        preview_size: preview_size as usize,
+        preview: None,
+    };
+
+    let premultiply = premultiplied && convert_alpha;
+    unwrap_or_return_err!(write_samples(WriteTarget::File(path_str), width, height, &options, input_format, output_format, premultiply, data, len));
+    ExrStatus::Ok
+}
+
+/// Writes like `write_texture`, taking every attribute from the header of layer `source_layer_name` of the EXR file at
+/// `source_path` instead of parameters, so pixels loaded with `load_from_path` and saved again keep their owner,
+/// comments, chromaticities, time code, display window, data window position, layer name, alpha mode and custom
+/// attributes. Null takes the first layer. The image level attributes of the source, such as the display window,
+/// are kept even when the written pixels have another size.
+///
+/// # Safety
+/// `path` and `source_path` must be valid nul-terminated strings, `source_layer_name` and the names null or valid
+/// nul-terminated strings, and `data` must point to `width * height * num_channels` samples of `input_format`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_texture_with_source_attrs(
+    path: *const c_char, source_path: *const c_char, source_layer_name: *const c_char, width: i32, height: i32,
+    num_channels: u32, channel_names: *const *const c_char, input_format: ExrPixelFormat, output_format: ExrPixelFormat,
+    encoding: ExrEncoding, dwa_compression_level: f32, data: *const c_void
+) -> ExrStatus {
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let source_path = unwrap_or_return_err!(CStr::from_ptr(source_path).to_str());
+
+    if width <= 0 || height <= 0 {
+        set_last_error(format!("invalid image size {}x{}", width, height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let (width, height, num_channels) = (width as usize, height as usize, num_channels as usize);
+
+    let (meta, layer_index) = match find_layer(ReadSource::File(source_path), read_optional_str(source_layer_name), false) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+    let header = &meta.headers[layer_index];
+    let (shared, own) = (&header.shared_attributes, &header.own_attributes);
+
+    let channel_names = read_str_array(channel_names, num_channels).unwrap_or_else(|| default_channel_names(num_channels, input_format));
+    if channel_names.len() != num_channels {
+        set_last_error(format!("{} channels need channel names", num_channels));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let options = WriteOptions {
+        channel_names,
+        encoding,
+        dwa_compression_level,
+        layer_name: own.layer_name.as_ref().map_or_else(|| DEFAULT_LAYER_NAME.to_string(), Text::to_string),
+        owner: own.owner.as_ref().map(Text::to_string),
+        comments: own.comments.as_ref().map(Text::to_string),
+        capture_date: own.capture_date.as_ref().map(Text::to_string),
+        chromaticities: shared.chromaticities,
+        data_window_position: own.layer_position,
+        display_window: Some(shared.display_window),
+        time_code: shared.time_code,
+        pixel_aspect_ratio: shared.pixel_aspect,
+        premultiplied: !own.other.contains_key(&Text::from(UNASSOCIATED_ALPHA_ATTRIBUTE)),
+        source_attributes: Some((shared, own)),
+        ..Default::default()
+    };
+
+    let len = width * height * num_channels;
+    unwrap_or_return_err!(write_samples(WriteTarget::File(path_str), width, height, &options, input_format, output_format, false, data, len));
+    ExrStatus::Ok
+}
+
+/// Writes a normal pass like `write_texture`, making sure its signed components are stored exactly. `encoding` must be
+/// lossless for `output_format`, Unknown keeping `input_format`, so DWAA and DWAB are rejected for float samples, PXR24
+/// for F32 and B44 and B44A for F16 with `ExrStatus::InvalidArgument`. Only narrowing F32 input to F16 output rounds
+/// samples, which is left to the caller. The channel names default to "N.X", "N.Y" and "N.Z" for 3 channels.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `channel_names` null or `num_channels` valid nul-terminated strings
+/// and `data` must point to `width * height * num_channels` samples of `input_format`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_normals(
+    path: *const c_char, width: i32, height: i32, num_channels: u32, channel_names: *const *const c_char,
+    input_format: ExrPixelFormat, output_format: ExrPixelFormat, encoding: ExrEncoding, data: *const c_void
+) -> ExrStatus {
+    let stored_format = if output_format == ExrPixelFormat::Unknown { input_format } else { output_format };
+    if !is_lossless(encoding, stored_format) {
+        set_last_error(format!("{:?} does not store {:?} normals exactly", encoding, stored_format));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let default_names = [c"N.X", c"N.Y", c"N.Z"].map(CStr::as_ptr);
+    let channel_names = if channel_names.is_null() && num_channels == 3 { default_names.as_ptr() } else { channel_names };
 
+    let options = ExrWriteOptions { output_format, encoding, ..Default::default() };
+    write_texture(path, width, height, num_channels, channel_names, input_format, &options, data)
+}
+
+/// Writes a depth pass the way EXR stores depth, as a single F32 channel named "Z". `data` holds one sample per pixel
+/// of F32 or F16, which is widened exactly, other formats being rejected with `ExrStatus::InvalidArgument` like
+/// `encoding`s that would round F32 samples, i.e. DWAA, DWAB and PXR24. Infinite depth, as used for the background,
+/// is kept. `load_from_path` returns such a file as one F32 channel named "Z", which the sRGB transfers leave alone
+/// and `has_alpha` does not take for alpha, and `load_layer_matching` finds it with the pattern "Z".
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string and `data` must point to `width * height` samples of `input_format`.
+#[no_mangle]
+pub unsafe extern "C" fn write_depth(
+    path: *const c_char, width: i32, height: i32, input_format: ExrPixelFormat, encoding: ExrEncoding, data: *const c_void
+) -> ExrStatus {
+    if !matches!(input_format, ExrPixelFormat::F32 | ExrPixelFormat::F16) {
+        set_last_error(format!("depth is written from F32 or F16 samples, got {:?}", input_format));
+        return ExrStatus::InvalidArgument;
+    }
+
+    if !is_lossless(encoding, ExrPixelFormat::F32) {
+        set_last_error(format!("{:?} does not store F32 depth exactly", encoding));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let channel_names = [c"Z".as_ptr()];
+    let options = ExrWriteOptions { output_format: ExrPixelFormat::F32, encoding, ..Default::default() };
+    write_texture(path, width, height, 1, channel_names.as_ptr(), input_format, &options, data)
+}
+
+/// Writes like `write_texture`, then reads the file back and compares it with `data` for quality control of
+/// deliverables. `max_observed_error` receives the largest absolute difference of a sample, measured against `data`
+/// stored as `output_format`, so narrowing F32 to F16 does not count. Lossless encodings must return every sample
+/// exactly, NaNs included, while lossy ones may differ by up to `max_error`. Otherwise the file is left for
+/// inspection and `ExrStatus::VerificationFailed` is returned. The file is read back pedantically, so anything
+/// exr would only load leniently fails the verification as well. `max_observed_error` is -1 unless the file was
+/// read back.
+///
+/// # Safety
+/// Same as `write_texture`, and `max_observed_error` must be writable.
 #[no_mangle]
-pub unsafe extern fn write_texture(path: *const c_char, width: i32, height: i32, format: i32, data: *const Sample) {
-    let path_str = CStr::from_ptr(path).to_str().unwrap();
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_texture_verified(
+    path: *const c_char, width: i32, height: i32, num_channels: u32, channel_names: *const *const c_char,
+    input_format: ExrPixelFormat, output_format: ExrPixelFormat, encoding: ExrEncoding, dwa_compression_level: f32,
+    max_error: f32, max_observed_error: *mut f32, data: *const c_void
+) -> ExrStatus {
+    *max_observed_error = -1.0;
+
+    if max_error.is_nan() || max_error < 0.0 {
+        set_last_error(format!("invalid maximum error {}", max_error));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let options = ExrWriteOptions { output_format, encoding, dwa_compression_level, ..Default::default() };
+    let status = write_texture(path, width, height, num_channels, channel_names, input_format, &options, data);
+    if status != ExrStatus::Ok {
+        return status;
+    }
+
+    let (mut loaded_width, mut loaded_height, mut loaded_channels, mut loaded_format) = (0, 0, 0, ExrPixelFormat::Unknown);
+    let (mut loaded_names, mut loaded) = (std::ptr::null_mut(), std::ptr::null_mut());
+    let load_options = ExrLoadOptions { pedantic: true, force_exr: true, ..Default::default() };
+    let status = load_from_path(
+        path, std::ptr::null(), &load_options, &mut loaded_width, &mut loaded_height, &mut loaded_channels, &mut loaded_format,
+        &mut loaded_names, std::ptr::null_mut(), 0, std::ptr::null_mut(), &mut loaded
+    );
+    if status != ExrStatus::Ok {
+        return status;
+    }
+
+    let names = CStr::from_ptr(loaded_names).to_string_lossy().into_owned();
+    free_string(loaded_names);
 
+    let (width, height, num_channels) = (width as usize, height as usize, num_channels as usize);
+    let (loaded_width, loaded_height, loaded_channels) = (loaded_width as usize, loaded_height as usize, loaded_channels as usize);
+    let loaded_samples = samples_as_f64(loaded, loaded_width * loaded_height * loaded_channels, loaded_format);
+    free_texture(loaded, loaded_width as u32, loaded_height as u32, loaded_channels as u32, loaded_format);
+
+    if (loaded_width, loaded_height) != (width, height) {
+        set_last_error(format!("{}x{} pixels were written but {}x{} read back", width, height, loaded_width, loaded_height));
+        return ExrStatus::VerificationFailed;
+    }
+
+    let stored_format = if output_format == ExrPixelFormat::Unknown { input_format } else { output_format };
+    let mut samples = samples_as_f64(data, width * height * num_channels, input_format);
+    if stored_format == ExrPixelFormat::F16 {
+        samples.iter_mut().for_each(|sample| *sample = f16::from_f64(*sample).to_f64());
+    }
+
+    // Channels are read back in another order, and a filled in alpha channel has nothing to be compared with
+    let source_names = read_str_array(channel_names, num_channels).unwrap_or_else(|| default_channel_names(num_channels, input_format));
+    let mut observed = 0.0_f64;
+    for (loaded_channel, name) in names.split('\n').enumerate() {
+        let Some(channel) = source_names.iter().position(|source_name| source_name == name) else {
+            continue;
+        };
+
+        for pixel in 0..width * height {
+            let (expected, actual) = (samples[pixel * num_channels + channel], loaded_samples[pixel * loaded_channels + loaded_channel]);
+            let error = if expected == actual || (expected.is_nan() && actual.is_nan()) { 0.0 } else { (expected - actual).abs() };
+            observed = observed.max(if error.is_nan() { f64::INFINITY } else { error });
+        }
+    }
+
+    *max_observed_error = observed as f32;
+    let allowed = if is_lossless(encoding, stored_format) { 0.0 } else { max_error as f64 };
+    if observed > allowed {
+        set_last_error(format!("samples read back differ by up to {}, allowed are {}", observed, allowed));
+        return ExrStatus::VerificationFailed;
+    }
+
+    ExrStatus::Ok
+}
+
+unsafe fn samples_as_f64(data: *const c_void, len: usize, format: ExrPixelFormat) -> Vec<f64> {
     match format {
-        0 => { // U32
-            let ptr = data as *const u32;
-            let array = from_raw_parts(ptr, (width * height * 4) as usize);
-            write_rgba_file(
-                path_str,
-                width as usize, height as usize,
-                |x,y| (
-                    array[(y * (width as usize) + x) * 4 + 0],
-                    array[(y * (width as usize) + x) * 4 + 1],
-                    array[(y * (width as usize) + x) * 4 + 2],
-                    array[(y * (width as usize) + x) * 4 + 3]
-                )
-            ).unwrap();
-        },
-        1 => { // F16
-            let ptr = data as *const f16;
-            let array = from_raw_parts(ptr, (width * height * 4) as usize);
-            write_rgba_file(
-                path_str,
-                width as usize, height as usize,
-                |x,y| (
-                    array[(y * (width as usize) + x) * 4 + 0],
-                    array[(y * (width as usize) + x) * 4 + 1],
-                    array[(y * (width as usize) + x) * 4 + 2],
-                    array[(y * (width as usize) + x) * 4 + 3]
-                )
-            ).unwrap();
-        },
-        2 => { // F32
-            let ptr = data as *const f32;
-            let array = from_raw_parts(ptr, (width * height * 4) as usize);
-            write_rgba_file(
-                path_str,
-                width as usize, height as usize,
-                |x,y| (
-                    array[(y * (width as usize) + x) * 4 + 0],
-                    array[(y * (width as usize) + x) * 4 + 1],
-                    array[(y * (width as usize) + x) * 4 + 2],
-                    array[(y * (width as usize) + x) * 4 + 3]
-                )
-            ).unwrap();
-        }
-        _ => { // Unknown
-        }
-    }
-}
-
-#[no_mangle]
-pub unsafe extern fn load_from_path(path: *const c_char, width: *mut i32, height: *mut i32, format: *mut i32) -> *mut [Sample;4] {
-    let path_str = CStr::from_ptr(path).to_str().unwrap();
-    let extension = Path::new(path_str).extension().unwrap().to_str().unwrap();
-
-    match extension {
-        "hdr" => {
-            let r = BufReader::new(File::open(path_str).unwrap());
-            let mut image = radiant::load(r).unwrap();
-
-            *width = image.width as i32;
-            *height = image.height as i32;
-            *format = 3;
-
-            let ptr = image.data.as_mut_ptr();
-            mem::forget(image);
-
-            ptr as *mut [Sample;4]
+        ExrPixelFormat::U32 => from_raw_parts(data as *const u32, len).iter().map(|&sample| sample as f64).collect(),
+        ExrPixelFormat::F16 => from_raw_parts(data as *const f16, len).iter().map(|sample| sample.to_f64()).collect(),
+        _ => from_raw_parts(data as *const f32, len).iter().map(|&sample| sample as f64).collect(),
+    }
+}
+
+// Whether `encoding` keeps every sample of `format` as it is
+fn is_lossless(encoding: ExrEncoding, format: ExrPixelFormat) -> bool {
+    match encoding {
+        ExrEncoding::DWAA | ExrEncoding::DWAB => format == ExrPixelFormat::U32,
+        ExrEncoding::PXR24 => format != ExrPixelFormat::F32,
+        ExrEncoding::B44 | ExrEncoding::B44A => format != ExrPixelFormat::F16,
+        _ => true,
+    }
+}
+
+// Writes `len` samples of `input_format` stored as `output_format`, Unknown keeping the input format. `premultiply`
+// multiplies color by alpha first, for F16 and F32 data
+#[allow(clippy::too_many_arguments)]
+unsafe fn write_samples(
+    target: WriteTarget, width: usize, height: usize, options: &WriteOptions, input_format: ExrPixelFormat,
+    output_format: ExrPixelFormat, premultiply: bool, data: *const c_void, len: usize
+) -> Result<()> {
+    let output_format = if output_format == ExrPixelFormat::Unknown { input_format } else { output_format };
+    match (input_format, output_format) {
+        (ExrPixelFormat::U32, ExrPixelFormat::U32) => write_exr(target, width, height, options, from_raw_parts(data as *const u32, len)),
+        (ExrPixelFormat::F16, ExrPixelFormat::F16) => {
+            let data = from_raw_parts(data as *const f16, len);
+            let converted = if premultiply { premultiply_alpha(data, width, options) } else { None };
+            write_exr(target, width, height, options, converted.as_deref().unwrap_or(data))
+        },
+        (ExrPixelFormat::F32, ExrPixelFormat::F32) => {
+            let data = from_raw_parts(data as *const f32, len);
+            let converted = if premultiply { premultiply_alpha(data, width, options) } else { None };
+            write_exr(target, width, height, options, converted.as_deref().unwrap_or(data))
         },
+        (ExrPixelFormat::F32, ExrPixelFormat::F16) => {
+            // premultiply before narrowing so the color channels keep full precision until they are stored
+            let data = from_raw_parts(data as *const f32, len);
+            let converted = if premultiply { premultiply_alpha(data, width, options) } else { None };
+            let narrowed: Vec<f16> = converted.as_deref().unwrap_or(data).iter().map(|&sample| f16::from_f32(sample)).collect();
+            write_exr(target, width, height, options, &narrowed)
+        },
+        (ExrPixelFormat::F16, ExrPixelFormat::F32) => {
+            let widened = widen_f16(from_raw_parts(data as *const f16, len));
+            let converted = if premultiply { premultiply_alpha(&widened, width, options) } else { None };
+            write_exr(target, width, height, options, converted.as_deref().unwrap_or(&widened))
+        },
+        (ExrPixelFormat::U32 | ExrPixelFormat::F16 | ExrPixelFormat::F32, ExrPixelFormat::U32 | ExrPixelFormat::F16 | ExrPixelFormat::F32) => {
+            Err(Error::NotSupported(format!("storing {:?} samples as {:?}", input_format, output_format).into()))
+        },
+        _ => Err(Error::NotSupported("pixel format".into())),
+    }
+}
+
+/// Rounds `len` samples of `format` in place to the nearest value with `mantissa_bits` mantissa bits, ties going to
+/// even, so the precision a lossy encoding keeps can be chosen up front. `F32` samples have up to 23 bits and `F16` up
+/// to 10, keeping 15 bits of an `F32` matching what `PXR24` stores. Infinities and NaNs are left alone.
+///
+/// # Safety
+/// `data` must point to `len` writable samples of `format`.
+#[no_mangle]
+pub unsafe extern "C" fn quantize_samples(data: *mut c_void, len: usize, format: ExrPixelFormat, mantissa_bits: u32) -> ExrStatus {
+    let max_bits = match format {
+        ExrPixelFormat::F32 | ExrPixelFormat::RGBF32 => 23,
+        ExrPixelFormat::F16 => 10,
         _ => {
-            match MetaData::read_from_file(path_str, false) {
-                Ok(meta) => {
-                    let size = meta.headers[0].layer_size;
-                    *width = size.0 as i32;
-                    *height = size.1 as i32;
-                
-                    let sample_type = meta.headers[0].channels.uniform_sample_type;
-                
-                    match sample_type {
-                        Some(v) => {
-                            *format = v as i32;
-        
-                            match v {
-                                SampleType::F16 => load_exr_f16(path_str) as *mut [Sample;4],
-                                SampleType::F32 => load_exr_f32(path_str) as *mut [Sample;4],
-                                SampleType::U32 => load_exr_u32(path_str) as *mut [Sample;4]
-                            }
-                        },
-                        None => {
-                            *format = -1;
-                            std::ptr::null_mut() as *mut [Sample;4]
-                        }
-                    }
-                },
-                Err(_e) => {
-                    *width = -1;
-                    *height = -1;
-                    *format = -1;
-        
-                    std::ptr::null_mut() as *mut [Sample;4]
-                }
-            }
+            set_last_error("only F16 and F32 samples can be quantized");
+            return ExrStatus::Unsupported;
         }
+    };
+
+    if mantissa_bits > max_bits {
+        set_last_error(format!("{:?} samples have no more than {} mantissa bits", format, max_bits));
+        return ExrStatus::InvalidArgument;
     }
-}
 
-fn load_exr_f16(path: &str) -> usize {
-    let image = read_first_rgba_layer_from_file(
-        path,
-        |resolution, _| {
-            let default_pixel: [f16;4] = [f16::from_f32(0.0), f16::from_f32(0.0), f16::from_f32(0.0), f16::from_f32(1.0)];
-            let empty_line = vec![ default_pixel; resolution.width() ];
-            let empty_image = vec![ empty_line; resolution.height() ];
-            empty_image
+    let dropped = max_bits - mantissa_bits;
+    match format {
+        ExrPixelFormat::F16 => for sample in from_raw_parts_mut(data as *mut f16, len) {
+            if sample.is_finite() {
+                *sample = f16::from_bits(round_mantissa(sample.to_bits() as u32, dropped) as u16);
+            }
         },
-        |pixel_vector, position, (r,g,b, a): (f16, f16, f16, f16)| {
-            pixel_vector[position.y()][position.x()] = [r, g, b, a]
+        _ => for sample in from_raw_parts_mut(data as *mut f32, len) {
+            if sample.is_finite() {
+                *sample = f32::from_bits(round_mantissa(sample.to_bits(), dropped));
+            }
         },
+    }
+
+    ExrStatus::Ok
+}
 
-    ).unwrap();
+// Clears the low `dropped` bits of a float's bits, rounding to nearest even. A carry out of the mantissa bumps the
+// exponent, which is the correctly rounded result, up to infinity for the largest values
+fn round_mantissa(bits: u32, dropped: u32) -> u32 {
+    if dropped == 0 {
+        return bits;
+    }
 
-    let mut pixel = image.layer_data.channel_data.pixels.into_iter().flatten().collect::<Vec<[f16;4]>>();
-    let ptr = pixel.as_mut_ptr();
-    mem::forget(pixel);
-    
-    return unsafe { mem::transmute(ptr) };
+    let half = 1 << (dropped - 1);
+    let kept_lsb = (bits >> dropped) & 1;
+    let rounded = bits + half - 1 + kept_lsb;
+    rounded & !((1 << dropped) - 1)
 }
 
-fn load_exr_f32(path: &str) -> usize {
-    let image = read_first_rgba_layer_from_file(
-        path,
-        |resolution, _| {
-            let default_pixel: [f32;4] = [0.0, 0.0, 0.0, 1.0];
-            let empty_line = vec![ default_pixel; resolution.width() ];
-            let empty_image = vec![ empty_line; resolution.height() ];
-            empty_image
-        },
-        |pixel_vector, position, (r,g,b, a): (f32, f32, f32, f32)| {
-            pixel_vector[position.y()][position.x()] = [r, g, b, a]
+/// Encodes RGBA pixels like `write_texture`, but into a buffer handed out through `out_buf` and `out_len`
+/// instead of a file. The buffer must be released with `free_buffer`.
+///
+/// # Safety
+/// `data` must point to `width * height * 4` samples of `format` and the out pointers must be writable.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_texture_to_memory(
+    width: i32, height: i32, format: ExrPixelFormat, encoding: ExrEncoding, data: *const c_void,
+    out_buf: *mut *mut u8, out_len: *mut usize
+) -> ExrStatus {
+    *out_len = 0;
+    *out_buf = std::ptr::null_mut();
+
+    if width <= 0 || height <= 0 {
+        set_last_error(format!("invalid image size {}x{}", width, height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let len = width * height * 4;
+
+    let options = WriteOptions { encoding, ..Default::default() };
+
+    let mut buffer = Vec::new();
+    let result = match format {
+        ExrPixelFormat::U32 => write_exr(WriteTarget::Memory(&mut buffer), width, height, &options, from_raw_parts(data as *const u32, len)),
+        ExrPixelFormat::F16 => write_exr(WriteTarget::Memory(&mut buffer), width, height, &options, from_raw_parts(data as *const f16, len)),
+        ExrPixelFormat::F32 => write_exr(WriteTarget::Memory(&mut buffer), width, height, &options, from_raw_parts(data as *const f32, len)),
+        _ => Err(Error::NotSupported("pixel format".into())),
+    };
+
+    match result {
+        Ok(()) => {
+            *out_len = buffer.len();
+            *out_buf = Box::into_raw(buffer.into_boxed_slice()) as *mut u8;
+            ExrStatus::Ok
         },
+        Err(e) => {
+            set_last_error(&e);
+            *out_len = 0;
+            *out_buf = std::ptr::null_mut();
+            ExrStatus::from(&e)
+        }
+    }
+}
 
-    ).unwrap();
+/// Releases a buffer returned by `write_texture_to_memory`.
+///
+/// # Safety
+/// `buf` must be null or come from `write_texture_to_memory` together with its `len`.
+#[no_mangle]
+pub unsafe extern "C" fn free_buffer(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        drop(Vec::from_raw_parts(buf, len, len));
+    }
+}
 
-    let mut pixel = image.layer_data.channel_data.pixels.into_iter().flatten().collect::<Vec<[f32;4]>>();
-    let ptr = pixel.as_mut_ptr();
-    mem::forget(pixel);
-    
-    return unsafe { mem::transmute(ptr) };
+// Where an encoded image ends up
+enum WriteTarget<'a> {
+    File(&'a str),
+    Memory(&'a mut Vec<u8>),
 }
 
-fn load_exr_u32(path: &str) -> usize {
-    let image = read_first_rgba_layer_from_file(
-        path,
-        |resolution, _| {
-            let default_pixel: [u32;4] = [0, 0, 0, 1];
-            let empty_line = vec![ default_pixel; resolution.width() ];
-            let empty_image = vec![ empty_line; resolution.height() ];
-            empty_image
-        },
-        |pixel_vector, position, (r,g,b, a): (u32, u32, u32, u32)| {
-            pixel_vector[position.y()][position.x()] = [r, g, b, a]
-        },
+impl WriteTarget<'_> {
+    fn write<'img, L: WritableLayers<'img>>(self, image: &'img Image<L>, options: &WriteOptions) -> Result<()> {
+        match self {
+            WriteTarget::File(path) => exr::io::attempt_delete_file_on_write_error(Path::new(path), |file| {
+                write_image(BufWriter::new(file), image, options)
+            }),
+            WriteTarget::Memory(buffer) => write_image(Cursor::new(buffer), image, options),
+        }
+    }
+}
+
+// 0 threads leave the count to exr, 1 compresses on the calling thread and anything else gets a pool of that size.
+// Blocks are handed to the compressor one at a time, so a cancelled write stops at the next block.
+fn write_image<'img, L: WritableLayers<'img>>(write: impl Write + Seek, image: &'img Image<L>, options: &WriteOptions) -> Result<()> {
+    let headers = image.layer_data.infer_headers(&image.attributes);
+    let layers = image.layer_data.create_writer(&headers);
+
+    exr::block::write(write, headers, true, |meta, chunk_writer| {
+        let mut chunk_writer = chunk_writer.on_progress(|progress| {
+            if let Some(callback) = options.progress {
+                callback(progress as f32);
+            }
+        });
+
+        let blocks = ordered_blocks(&meta.headers).map(|(index_in_header, block_index)| (index_in_header, UncompressedBlock {
+            index: block_index,
+            data: layers.extract_uncompressed_block(&meta.headers, block_index),
+        }));
+        let mut blocks = blocks.map(|block| if options.is_cancelled() { Err(Error::Aborted) } else { Ok(block) });
+
+        let num_threads = options.num_threads as usize;
+        let compressor = match num_threads {
+            0 => chunk_writer.parallel_blocks_compressor(&meta),
+            1 => None,
+            _ => {
+                let pool = || rayon::ThreadPoolBuilder::new().num_threads(num_threads).build();
+                ParallelBlocksCompressor::new_with_thread_pool(&meta, &mut chunk_writer, pool)
+            },
+        };
 
-    ).unwrap();
+        // exr only compresses in parallel when there is something to compress
+        match compressor {
+            Some(mut compressor) => blocks.try_for_each(|block| {
+                let (index_in_header, block) = block?;
+                compressor.add_block_to_compression_queue(index_in_header, block)
+            }),
+            None if num_threads > 1 && meta.headers.iter().any(|header| header.compression != Compression::Uncompressed) => {
+                Err(Error::NotSupported("compression thread pool".into()))
+            },
+            None => {
+                let mut compressor = chunk_writer.sequential_blocks_compressor(&meta);
+                blocks.try_for_each(|block| {
+                    let (index_in_header, block) = block?;
+                    compressor.compress_block(index_in_header, block)
+                })
+            },
+        }
+    })
+}
+
+// The blocks of every header in the order they are stored, each with its index in increasing y order. exr reverses
+// all tiles of a decreasing header, levels and columns included, while the spec keeps levels and columns increasing
+// and only visits the rows of each level bottom to top. Scan line blocks are rows of their own, so they simply reverse.
+fn ordered_blocks(headers: &[Header]) -> impl Iterator<Item = (usize, BlockIndex)> + '_ {
+    headers.iter().enumerate().flat_map(|(layer, header)| {
+        let mut tiles = header.blocks_increasing_y_order().enumerate().collect::<Vec<_>>();
+
+        if header.line_order == LineOrder::Decreasing {
+            tiles = tiles.chunk_by(|(_, a), (_, b)| a.location.level_index == b.location.level_index)
+                .flat_map(|level| level.chunk_by(|(_, a), (_, b)| a.location.tile_index.y() == b.location.tile_index.y()).rev())
+                .flatten()
+                .copied()
+                .collect();
+        }
+
+        tiles.into_iter().map(move |(index_in_header, tile)| {
+            let bounds = header.get_absolute_block_pixel_coordinates(tile.location).expect("tile coordinate bug");
+            (index_in_header, BlockIndex {
+                layer,
+                level: tile.location.level_index,
+                pixel_position: bounds.position.to_usize("data indices start").expect("data index bug"),
+                pixel_size: bounds.size,
+            })
+        })
+    })
+}
+
+#[derive(Clone)]
+struct WriteOptions<'a> {
+    channel_names: Vec<String>,
+    encoding: ExrEncoding,
+    dwa_compression_level: f32,
+    tile_size: Option<Vec2<usize>>,
+    block_mode: u32,
+    line_order: u32,
+    layer_name: String,
+    owner: Option<String>,
+    comments: Option<String>,
+    capture_date: Option<String>,
+    chromaticities: Option<Chromaticities>,
+    data_window_position: Vec2<i32>,
+    display_window: Option<IntegerBounds>,
+    time_code: Option<TimeCode>,
+    default_pixel: Option<[f32; 4]>,
+    pixel_aspect_ratio: f32,
+    // Samples from the start of one row of the interleaved data to the next, 0 for tightly packed rows
+    row_stride: usize,
+    // Whether the first row of the data is the bottom of the image
+    flip_vertical: bool,
+    num_threads: u32,
+    progress: Option<ExrProgressCallback>,
+    cancel: Option<&'a AtomicBool>,
+    premultiplied: bool,
+    attributes: Vec<(String, String)>,
+    // Headers every attribute not set by the fields above is taken from, instead of being left out
+    source_attributes: Option<(&'a ImageAttributes, &'a LayerAttributes)>,
+    // The longer edge of the preview embedded in the header, 0 for none
+    preview_size: usize,
+    // The preview `write_exr` made of the samples to be stored, which every layout of the layer keeps
+    preview: Option<Preview>,
+}
+
+impl Default for WriteOptions<'_> {
+    fn default() -> Self {
+        WriteOptions {
+            channel_names: default_channel_names(4, ExrPixelFormat::F32),
+            encoding: ExrEncoding::RLE,
+            dwa_compression_level: DEFAULT_DWA_COMPRESSION_LEVEL,
+            tile_size: None,
+            block_mode: BLOCK_MODE_AUTO,
+            line_order: LINE_ORDER_AUTO,
+            layer_name: DEFAULT_LAYER_NAME.to_string(),
+            owner: None,
+            comments: None,
+            capture_date: None,
+            chromaticities: None,
+            data_window_position: Vec2(0, 0),
+            display_window: None,
+            time_code: None,
+            default_pixel: None,
+            pixel_aspect_ratio: 1.0,
+            row_stride: 0,
+            flip_vertical: false,
+            num_threads: 0,
+            progress: None,
+            cancel: None,
+            premultiplied: true,
+            attributes: Vec::new(),
+            source_attributes: None,
+            preview_size: 0,
+            preview: None,
+        }
+    }
+}
+
+impl WriteOptions<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
 
-    let mut pixel = image.layer_data.channel_data.pixels.into_iter().flatten().collect::<Vec<[u32;4]>>();
-    let ptr = pixel.as_mut_ptr();
-    mem::forget(pixel);
-    
-    return unsafe { mem::transmute(ptr) };
+// EXR text is Latin-1, anything else is rejected instead of panicking inside exr
+fn exr_text(string: &str) -> Result<Text> {
+    Text::new_or_none(string).ok_or_else(|| Error::NotSupported(format!("non Latin-1 text \"{}\"", string).into()))
 }
 
-// The use of exr::Sample is stored in memory at compile time according to the largest element, f32
+fn image_attributes(options: &WriteOptions, width: usize, height: usize) -> ImageAttributes {
+    let source = options.source_attributes.map_or_else(|| ImageAttributes::with_size((width, height)), |(image, _)| image.clone());
 
-// fn load_exr(path: &str) -> usize {
-//     let image = read_first_rgba_layer_from_file(
-//         path,
-//         |resolution, _| {
-//             let default_pixel: [Sample;4] = [Sample::default(), Sample::default(), Sample::default(), Sample::default()];
-//             let empty_line = vec![ default_pixel; resolution.width() ];
-//             let empty_image = vec![ empty_line; resolution.height() ];
-//             empty_image
-//         },
-//         |pixel_vector, position, (r,g,b, a): (Sample, Sample, Sample, Sample)| {
-//             pixel_vector[position.y()][position.x()] = [r, g, b, a]
-//         },
+    ImageAttributes {
+        chromaticities: options.chromaticities,
+        pixel_aspect: options.pixel_aspect_ratio,
+        time_code: options.time_code,
+        display_window: options.display_window.unwrap_or_else(|| IntegerBounds::new(options.data_window_position, Vec2(width, height))),
+        ..source
+    }
+}
+
+fn layer_attributes(options: &WriteOptions, layer_name: &str) -> Result<LayerAttributes> {
+    let optional_text = |string: &Option<String>| string.as_deref().map(exr_text).transpose();
+    let source = match options.source_attributes {
+        Some((_, layer)) => LayerAttributes { layer_name: Some(exr_text(layer_name)?), ..layer.clone() },
+        None => LayerAttributes::named(exr_text(layer_name)?),
+    };
+
+    let mut other = source.other.clone();
+    for (name, value) in &options.attributes {
+        other.insert(exr_text(name)?, AttributeValue::Text(exr_text(value)?));
+    }
+
+    if options.premultiplied {
+        other.remove(&Text::from(UNASSOCIATED_ALPHA_ATTRIBUTE));
+    } else {
+        other.insert(Text::from(UNASSOCIATED_ALPHA_ATTRIBUTE), AttributeValue::I32(1));
+    }
+
+    Ok(LayerAttributes {
+        other,
+        owner: optional_text(&options.owner)?,
+        comments: optional_text(&options.comments)?,
+        capture_date: optional_text(&options.capture_date)?,
+        layer_position: options.data_window_position,
+        preview: options.preview.clone().or_else(|| source.preview.clone()),
+        ..source
+    })
+}
+
+const BLOCK_MODE_AUTO: u32 = 0;
+const BLOCK_MODE_SCAN_LINES: u32 = 1;
+const BLOCK_MODE_TILES: u32 = 2;
+
+const LEVEL_MODE_SINGULAR: u32 = 0;
+const LEVEL_MODE_MIP_MAP: u32 = 1;
+const LEVEL_MODE_RIP_MAP: u32 = 2;
+
+const ROUNDING_MODE_DOWN: u32 = 0;
+const ROUNDING_MODE_UP: u32 = 1;
 
-//     ).unwrap();
+const LINE_ORDER_AUTO: u32 = 0;
+const LINE_ORDER_INCREASING: u32 = 1;
+const LINE_ORDER_DECREASING: u32 = 2;
+const LINE_ORDER_UNSPECIFIED: u32 = 3;
+
+const DEFAULT_LAYER_NAME: &str = "first layer";
+
+fn exr_rounding_mode(rounding_mode: u32) -> Option<RoundingMode> {
+    match rounding_mode {
+        ROUNDING_MODE_DOWN => Some(RoundingMode::Down),
+        ROUNDING_MODE_UP => Some(RoundingMode::Up),
+        _ => None,
+    }
+}
 
-//     let mut pixel = image.layer_data.channel_data.pixels.into_iter().flatten().collect::<Vec<[Sample;4]>>();
-//     let ptr = pixel.as_mut_ptr();
-//     mem::forget(pixel);
-    
-//     return unsafe { mem::transmute(ptr) };
-// }
+// Whether the RGBA levels of `level_sizes` hold the samples `level_lens` tells, a null `level_lens` accepting any
+unsafe fn level_lens_match(level_lens: *const usize, level_sizes: &[Vec2<usize>]) -> bool {
+    level_lens.is_null() || from_raw_parts(level_lens, level_sizes.len()).iter().zip(level_sizes)
+        .all(|(&len, level_size)| len == level_size.area() * 4)
+}
+
+// How many samples apart rows of `row_len` samples start for a stride given in bytes, 0 meaning tightly packed rows.
+// None when the stride cannot hold a row or splits a sample.
+fn row_stride_samples(row_len: usize, element_size: usize, row_stride_bytes: usize) -> Option<usize> {
+    match row_stride_bytes {
+        0 => Some(row_len),
+        _ if element_size == 0 || !row_stride_bytes.is_multiple_of(element_size) => None,
+        _ => Some(row_stride_bytes / element_size).filter(|&row_stride| row_stride >= row_len),
+    }
+}
+
+// The samples `rows` rows starting `row_stride` apart span, the last row ending right after its pixels
+fn strided_len(row_len: usize, row_stride: usize, rows: usize) -> usize {
+    rows.checked_sub(1).map_or(0, |rows| rows * row_stride + row_len)
+}
+
+// The rows of interleaved samples starting `row_stride` apart without their padding, 0 meaning tightly packed rows
+fn rows<T>(data: &[T], row_len: usize, row_stride: usize) -> impl Iterator<Item = &[T]> {
+    let row_stride = if row_stride == 0 { row_len } else { row_stride };
+    data.chunks(row_stride.max(1)).map(move |row| &row[..row_len.min(row.len())])
+}
+
+fn rows_mut<T>(data: &mut [T], row_len: usize, row_stride: usize) -> impl Iterator<Item = &mut [T]> {
+    let row_stride = if row_stride == 0 { row_len } else { row_stride };
+    data.chunks_mut(row_stride.max(1)).map(move |row| {
+        let len = row_len.min(row.len());
+        &mut row[..len]
+    })
+}
+
+// OpenImageIO's marker for color channels that are not multiplied by alpha
+const UNASSOCIATED_ALPHA_ATTRIBUTE: &str = "oiio:UnassociatedAlpha";
+
+// Multiplies the channels named R, G and B by the one named A, None when there is no alpha
+fn premultiply_alpha<T: IntoNativeSample + FromNativeSample>(data: &[T], width: usize, options: &WriteOptions) -> Option<Vec<T>> {
+    let channel_names = &options.channel_names;
+    let alpha = channel_names.iter().position(|name| name == "A")?;
+    let colors = channel_names.iter().enumerate()
+        .filter(|(_, name)| matches!(name.as_str(), "R" | "G" | "B"))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    let mut converted = data.to_vec();
+    let row_len = width * channel_names.len();
+    for pixel in rows_mut(&mut converted, row_len, options.row_stride).flat_map(|row| row.chunks_exact_mut(channel_names.len())) {
+        let alpha = pixel[alpha].to_f32();
+        for &color in &colors {
+            pixel[color] = T::from_f32(pixel[color].to_f32() * alpha);
+        }
+    }
+
+    Some(converted)
+}
+
+fn exr_encoding(options: &WriteOptions) -> Result<Encoding> {
+    let dwa_level = Some(options.dwa_compression_level.max(0.0));
+
+    let (compression, blocks, line_order) = match options.encoding {
+        ExrEncoding::Uncompressed => (Compression::Uncompressed, Blocks::ScanLines, LineOrder::Increasing),
+        ExrEncoding::RLE => (Compression::RLE, Blocks::Tiles(Vec2(64, 64)), LineOrder::Unspecified),
+        ExrEncoding::ZIP1 => (Compression::ZIP1, Blocks::ScanLines, LineOrder::Increasing),
+        ExrEncoding::PIZ => (Compression::PIZ, Blocks::Tiles(Vec2(256, 256)), LineOrder::Unspecified),
+        ExrEncoding::DWAA => (Compression::DWAA(dwa_level), Blocks::ScanLines, LineOrder::Increasing),
+        ExrEncoding::DWAB => (Compression::DWAB(dwa_level), Blocks::Tiles(Vec2(256, 256)), LineOrder::Unspecified),
+        // Only F32 samples are rounded to 24 bits, F16 and U32 samples are compressed losslessly like ZIP
+        ExrEncoding::PXR24 => (Compression::PXR24, Blocks::ScanLines, LineOrder::Increasing),
+        ExrEncoding::B44 => (Compression::B44, Blocks::Tiles(Vec2(64, 64)), LineOrder::Unspecified),
+        ExrEncoding::B44A => (Compression::B44A, Blocks::Tiles(Vec2(64, 64)), LineOrder::Unspecified),
+        ExrEncoding::ZIP16 | ExrEncoding::Auto => (Compression::ZIP16, Blocks::ScanLines, LineOrder::Increasing),
+        ExrEncoding::Other => return Err(Error::NotSupported("writing with encoding Other".into())),
+    };
+
+    let blocks = match (options.block_mode, options.tile_size, blocks) {
+        (BLOCK_MODE_SCAN_LINES, _, _) => Blocks::ScanLines,
+        (_, Some(tile_size), _) => Blocks::Tiles(tile_size),
+        (BLOCK_MODE_TILES, None, Blocks::ScanLines) => Blocks::Tiles(Vec2(64, 64)),
+        (_, None, blocks) => blocks,
+    };
+
+    let line_order = match options.line_order {
+        LINE_ORDER_INCREASING => LineOrder::Increasing,
+        LINE_ORDER_DECREASING => LineOrder::Decreasing,
+        LINE_ORDER_UNSPECIFIED => LineOrder::Unspecified,
+        _ => line_order,
+    };
+
+    // Scan line images must specify their line order
+    let line_order = match (blocks, line_order) {
+        (Blocks::ScanLines, LineOrder::Unspecified) => LineOrder::Increasing,
+        (_, line_order) => line_order,
+    };
+
+    Ok(Encoding { compression, blocks, line_order })
+}
+
+fn write_exr<T: IntoSample + FromNativeSample + Copy + PartialEq>(target: WriteTarget, width: usize, height: usize, options: &WriteOptions, data: &[T]) -> Result<()> {
+    let row_len = width * options.channel_names.len();
+    let row_stride = if options.row_stride == 0 { row_len } else { options.row_stride };
+
+    let chosen;
+    let options = if options.encoding == ExrEncoding::Auto {
+        chosen = WriteOptions { encoding: auto_encoding(data, T::PREFERRED_SAMPLE_TYPE, options.channel_names.len(), row_len, row_stride), ..options.clone() };
+        &chosen
+    } else {
+        options
+    };
+
+    let names = &options.channel_names;
+    // Where the samples of row `y` of the image start in `data`. The pixel closures get the position of a pixel
+    // within the layer, also for tiles cut off at the edges, so every block layout reads the same samples
+    let row_start = |y: usize| if options.flip_vertical { height - 1 - y } else { y } * row_stride;
+
+    let filled = options.default_pixel.and_then(|default_pixel| fill_non_finite(data, names.len(), row_len, row_stride, default_pixel));
+    let data = filled.as_deref().unwrap_or(data);
+
+    let previewed;
+    let options = if options.preview_size > 0 && !names.is_empty() {
+        let preview = preview_image(data, Vec2(width, height), names.len(), row_start, options.preview_size);
+        previewed = WriteOptions { preview: Some(preview), ..options.clone() };
+        &previewed
+    } else {
+        options
+    };
+
+    let attributes = layer_attributes(options, &options.layer_name)?;
+    let encoding = exr_encoding(options)?;
+
+    match names.len() {
+        1 => {
+            let channels = SpecificChannels::build()
+                .with_channel_details::<T>(channel_description(&names[0], T::PREFERRED_SAMPLE_TYPE))
+                .with_pixel_fn(|Vec2(x, y)| (data[row_start(y) + x],));
+
+            target.write(&Image::new(image_attributes(options, width, height), Layer::new((width, height), attributes, encoding, channels)), options)
+        },
+        2 => {
+            let channels = SpecificChannels::build()
+                .with_channel_details::<T>(channel_description(&names[0], T::PREFERRED_SAMPLE_TYPE))
+                .with_channel_details::<T>(channel_description(&names[1], T::PREFERRED_SAMPLE_TYPE))
+                .with_pixel_fn(|Vec2(x, y)| {
+                    let i = row_start(y) + x * 2;
+                    (data[i], data[i + 1])
+                });
+
+            target.write(&Image::new(image_attributes(options, width, height), Layer::new((width, height), attributes, encoding, channels)), options)
+        },
+        4 => {
+            let channels = SpecificChannels::build()
+                .with_channel_details::<T>(channel_description(&names[0], T::PREFERRED_SAMPLE_TYPE))
+                .with_channel_details::<T>(channel_description(&names[1], T::PREFERRED_SAMPLE_TYPE))
+                .with_channel_details::<T>(channel_description(&names[2], T::PREFERRED_SAMPLE_TYPE))
+                .with_channel_details::<T>(channel_description(&names[3], T::PREFERRED_SAMPLE_TYPE))
+                .with_pixel_fn(|Vec2(x, y)| {
+                    let i = row_start(y) + x * 4;
+                    (data[i], data[i + 1], data[i + 2], data[i + 3])
+                });
+
+            target.write(&Image::new(image_attributes(options, width, height), Layer::new((width, height), attributes, encoding, channels)), options)
+        },
+        0 => Err(Error::NotSupported("channel count".into())),
+        // Any other count, such as a stack of AOVs, is stored channel by channel
+        count => {
+            let packed = (0..height).flat_map(|y| &data[row_start(y)..][..row_len]).copied().collect::<Vec<_>>();
+            let planes = deinterleave(&packed, count).into_iter().map(|plane| match T::PREFERRED_SAMPLE_TYPE {
+                SampleType::F16 => FlatSamples::F16(plane.iter().map(|sample| sample.to_f16()).collect()),
+                SampleType::F32 => FlatSamples::F32(plane.iter().map(|sample| sample.to_f32()).collect()),
+                SampleType::U32 => FlatSamples::U32(plane.iter().map(|sample| sample.to_u32()).collect()),
+            }).collect();
+
+            write_planar_exr(target, width, height, options, planes)
+        }
+    }
+}
+
+// exr guesses that every channel but colors, luminance and depth is perceptually linear, which makes B44 pass
+// alpha through its logarithm and lose even opaque alpha. OpenEXR stores alpha as it is, leaving flat blocks exact
+fn channel_description(name: &str, sample_type: SampleType) -> ChannelDescription {
+    let channel = ChannelDescription::named(name, sample_type);
+    ChannelDescription { quantize_linearly: channel.quantize_linearly && !is_alpha(name), ..channel }
+}
+
+fn is_alpha(channel_name: &str) -> bool {
+    channel_name.rsplit('.').next() == Some("A")
+}
+
+// The lossless compression `ExrEncoding::Auto` stands for, judged by how many samples of the channels that change
+// repeat the sample of the pixel to their left
+fn auto_encoding<T: PartialEq>(data: &[T], sample_type: SampleType, num_channels: usize, row_len: usize, row_stride: usize) -> ExrEncoding {
+    if sample_type == SampleType::F32 || num_channels == 0 {
+        return ExrEncoding::ZIP16;
+    }
+
+    // Pairs of horizontal neighbours and how many of them are equal, per channel
+    let mut repeats = vec![(0_usize, 0_usize); num_channels];
+    for row in rows(data, row_len, row_stride) {
+        for (index, (left, right)) in row.iter().zip(row.iter().skip(num_channels)).enumerate() {
+            let (pairs, equal) = &mut repeats[index % num_channels];
+            *pairs += 1;
+            *equal += usize::from(left == right);
+        }
+    }
+
+    let (pairs, equal) = repeats.into_iter()
+        .filter(|&(pairs, equal)| equal < pairs)
+        .fold((0, 0), |(all_pairs, all_equal), (pairs, equal)| (all_pairs + pairs, all_equal + equal));
+
+    match sample_type {
+        SampleType::U32 if equal * 2 >= pairs => ExrEncoding::RLE,
+        SampleType::F16 if equal * 2 < pairs => ExrEncoding::PIZ,
+        _ => ExrEncoding::ZIP16,
+    }
+}
+
+// Each preview pixel averages the pixels of the image it covers. Channels are mapped like `write_png_preview` maps
+// them, so single channels become grey and RGB gets an opaque alpha. Of more than four channels, such as a stack of
+// AOVs, the first three are taken for RGB.
+fn preview_image<T: IntoNativeSample>(
+    data: &[T], size: Vec2<usize>, num_channels: usize, row_start: impl Fn(usize) -> usize, longest_edge: usize
+) -> Preview {
+    let scale = (longest_edge as f64 / size.width().max(size.height()) as f64).min(1.0);
+    let shrink = |edge: usize| ((edge as f64 * scale).round() as usize).max(1);
+    let preview_size = Vec2(shrink(size.width()), shrink(size.height()));
+    // The source pixels `index` spans along an edge of `edge` pixels, `preview_edge` of which make the preview
+    let span = |index: usize, edge: usize, preview_edge: usize| index * edge / preview_edge..((index + 1) * edge).div_ceil(preview_edge);
+
+    let encode = |sample: f32| to_unorm8(linear_to_srgb(sample.max(0.0)));
+    let mut pixel_data = Vec::with_capacity(preview_size.area() * 4);
+    for y in 0..preview_size.height() {
+        for x in 0..preview_size.width() {
+            let (columns, rows) = (span(x, size.width(), preview_size.width()), span(y, size.height(), preview_size.height()));
+            let count = (columns.len() * rows.len()) as f32;
+
+            let mut sums = [0.0f32; 4];
+            for row in rows {
+                for column in columns.clone() {
+                    let pixel = &data[row_start(row) + column * num_channels..][..num_channels];
+                    for (sum, sample) in sums.iter_mut().zip(pixel) {
+                        *sum += sample.to_f32();
+                    }
+                }
+            }
+
+            let [first, second, third, fourth] = sums.map(|sum| sum / count);
+            let rgba = match num_channels {
+                1 => [encode(first), encode(first), encode(first), u8::MAX],
+                2 => [encode(first), encode(second), 0, u8::MAX],
+                4 => [encode(first), encode(second), encode(third), to_unorm8(fourth)],
+                _ => [encode(first), encode(second), encode(third), u8::MAX],
+            };
+            pixel_data.extend(rgba.map(|value| value as i8));
+        }
+    }
+
+    Preview { size: preview_size, pixel_data }
+}
+
+// Replaces the samples that are not finite in the type they are stored as, returns `None` if there are none
+fn fill_non_finite<T: IntoNativeSample + FromNativeSample + Copy>(
+    data: &[T], num_channels: usize, row_len: usize, row_stride: usize, default_pixel: [f32; 4]
+) -> Option<Vec<T>> {
+    if num_channels == 0 || rows(data, row_len, row_stride).flatten().all(|sample| sample.to_f32().is_finite()) {
+        return None;
+    }
+
+    let defaults = (0..num_channels).map(|channel| T::from_f32(default_pixel[channel.min(3)])).collect::<Vec<_>>();
+    let mut filled = data.to_vec();
+    for pixel in rows_mut(&mut filled, row_len, row_stride).flat_map(|row| row.chunks_mut(num_channels)) {
+        for (sample, &default) in pixel.iter_mut().zip(&defaults) {
+            if !sample.to_f32().is_finite() {
+                *sample = default;
+            }
+        }
+    }
+
+    Some(filled)
+}
+
+/// Writes `num_channels` separate planes of `width * height` samples, each stored under the matching name
+/// of `channel_names`.
+///
+/// # Safety
+/// `path` and every name must be valid nul-terminated strings, and `planes` must hold `num_channels` pointers
+/// to `width * height` samples of `format`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_texture_planar(
+    path: *const c_char, width: i32, height: i32, format: ExrPixelFormat, encoding: ExrEncoding,
+    num_channels: u32, channel_names: *const *const c_char, planes: *const *const c_void
+) -> ExrStatus {
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+
+    if width <= 0 || height <= 0 {
+        set_last_error(format!("invalid image size {}x{}", width, height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let (width, height, num_channels) = (width as usize, height as usize, num_channels as usize);
+    let len = width * height;
+
+    let options = WriteOptions {
+        channel_names: read_str_array(channel_names, num_channels).unwrap_or_else(|| default_channel_names(num_channels, format)),
+        encoding,
+        ..Default::default()
+    };
+
+    let planes = from_raw_parts(planes, num_channels);
+    let samples = planes.iter().map(|plane| match format {
+        ExrPixelFormat::U32 => Some(FlatSamples::U32(from_raw_parts(*plane as *const u32, len).to_vec())),
+        ExrPixelFormat::F16 => Some(FlatSamples::F16(from_raw_parts(*plane as *const f16, len).to_vec())),
+        ExrPixelFormat::F32 => Some(FlatSamples::F32(from_raw_parts(*plane as *const f32, len).to_vec())),
+        _ => None,
+    }).collect::<Option<Vec<_>>>();
+
+    match samples {
+        Some(samples) => {
+            unwrap_or_return_err!(write_planar_exr(WriteTarget::File(path_str), width, height, &options, samples));
+            ExrStatus::Ok
+        },
+        None => {
+            set_last_error("unsupported pixel format");
+            ExrStatus::Unsupported
+        }
+    }
+}
+
+const DEFAULT_DWA_COMPRESSION_LEVEL: f32 = 45.0;
+
+fn write_planar_exr(target: WriteTarget, width: usize, height: usize, options: &WriteOptions, planes: Vec<FlatSamples>) -> Result<()> {
+    if options.channel_names.len() != planes.len() {
+        return Err(Error::Invalid("channel name count".into()));
+    }
+
+    let list = options.channel_names.iter().zip(planes)
+        .map(|(name, samples)| {
+            let channel = AnyChannel::new(name.as_str(), samples);
+            AnyChannel { quantize_linearly: channel.quantize_linearly && !is_alpha(name), ..channel }
+        })
+        .collect();
+
+    let layer = Layer::new(
+        (width, height),
+        layer_attributes(options, &options.layer_name)?,
+        exr_encoding(options)?,
+        AnyChannels::sort(list),
+    );
+
+    target.write(&Image::new(image_attributes(options, width, height), layer), options)
+}
+
+/// Writes `num_layers` RGBA layers of the same size into one file. Layer `i` is stored under `layer_names[i]`
+/// and reads `width * height * 4` interleaved samples of `formats[i]` from `datas[i]`. Each layer becomes a part of
+/// the file, which readers tell apart by name, so names that are empty or used twice are rejected with
+/// `ExrStatus::InvalidArgument` before anything is written.
+///
+/// # Safety
+/// `path` and every name must be valid nul-terminated strings, and `layer_names`, `formats` and `datas`
+/// must all hold `num_layers` elements.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_multilayer(
+    path: *const c_char, width: i32, height: i32, encoding: ExrEncoding, num_layers: u32,
+    layer_names: *const *const c_char, formats: *const ExrPixelFormat, datas: *const *const c_void
+) -> ExrStatus {
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+
+    if width <= 0 || height <= 0 {
+        set_last_error(format!("invalid image size {}x{}", width, height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let (width, height, num_layers) = (width as usize, height as usize, num_layers as usize);
+
+    let names = read_str_array(layer_names, num_layers).unwrap_or_default();
+    if names.len() != num_layers || names.iter().any(String::is_empty) {
+        set_last_error("every layer needs a name");
+        return ExrStatus::InvalidArgument;
+    }
+
+    if let Some(duplicate) = names.iter().enumerate().find_map(|(index, name)| names[..index].contains(name).then_some(name)) {
+        set_last_error(format!("layer name \"{}\" is used more than once", duplicate));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let formats = from_raw_parts(formats, num_layers);
+    let datas = from_raw_parts(datas, num_layers);
+
+    let options = WriteOptions { encoding, ..Default::default() };
+
+    let layers = names.iter().zip(formats).zip(datas).map(|((name, format), data)| {
+        let planes = deinterleave_planes(*data, width * height, 4, *format).ok_or_else(|| Error::NotSupported("pixel format".into()))?;
+        let list = options.channel_names.iter().zip(planes)
+            .map(|(channel_name, samples)| AnyChannel::new(channel_name.as_str(), samples))
+            .collect();
+
+        Ok(Layer::new((width, height), layer_attributes(&options, name)?, exr_encoding(&options)?, AnyChannels::sort(list)))
+    }).collect::<Result<Vec<_>>>();
+
+    let layers = unwrap_or_return_err!(layers);
+    unwrap_or_return_err!(WriteTarget::File(path_str).write(&Image::from_layers(image_attributes(&options, width, height), layers), &options));
+    ExrStatus::Ok
+}
+
+/// Writes a tiled RGBA image together with its mip chain, so readers can pick a resolution level without
+/// filtering. `rounding_mode` tells how odd sizes are halved, 0 rounding down as OpenEXR usually does and 1 rounding
+/// up, which some renderers expect. Rounding down, level `i` reads `max(1, base_width >> i) * max(1, base_height >> i) * 4`
+/// interleaved samples of `format` from `level_datas[i]`, while rounding up divides both sides by `2^i` rounding up.
+/// A full chain is required, `num_levels` being the number of halvings until both sides are 1 plus one, e.g. 11
+/// levels for 1024x512, and 3 for 5x5 rounding down but 4 rounding up.
+/// Unless `level_lens` is null, it holds the number of samples of each level, which must match the sizes the rounding
+/// mode gives to catch buffers made for the other convention.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `level_datas` must hold `num_levels` pointers to the pixels
+/// of each level and `level_lens` must be null or hold `num_levels` lengths.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_texture_mipmapped(
+    path: *const c_char, base_width: i32, base_height: i32, format: ExrPixelFormat, encoding: ExrEncoding,
+    rounding_mode: u32, num_levels: u32, level_datas: *const *const c_void, level_lens: *const usize
+) -> ExrStatus {
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+
+    if base_width <= 0 || base_height <= 0 {
+        set_last_error(format!("invalid image size {}x{}", base_width, base_height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let Some(rounding_mode) = exr_rounding_mode(rounding_mode) else {
+        set_last_error(format!("invalid rounding mode {}", rounding_mode));
+        return ExrStatus::InvalidArgument;
+    };
+
+    let size = Vec2(base_width as usize, base_height as usize);
+    let expected_levels = exr::meta::mip_map_levels(rounding_mode, size).count();
+    if num_levels as usize != expected_levels {
+        set_last_error(format!("{}x{} needs {} mip levels, got {}", base_width, base_height, expected_levels, num_levels));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let level_sizes = exr::meta::mip_map_levels(rounding_mode, size).map(|(_, level_size)| level_size).collect::<Vec<_>>();
+    if !level_lens_match(level_lens, &level_sizes) {
+        set_last_error(format!("mip levels of {}x{} do not have the sizes rounding {:?} gives", base_width, base_height, rounding_mode));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let options = WriteOptions { encoding, block_mode: BLOCK_MODE_TILES, ..Default::default() };
+    let level_datas = from_raw_parts(level_datas, expected_levels);
+
+    // One list of levels per channel, instead of one list of channels per level
+    let mut channel_levels: Vec<Vec<FlatSamples>> = vec![Vec::new(); 4];
+    for (level_size, data) in level_sizes.into_iter().zip(level_datas) {
+        let Some(planes) = deinterleave_planes(*data, level_size.area(), 4, format) else {
+            set_last_error("unsupported pixel format");
+            return ExrStatus::Unsupported;
+        };
+
+        for (levels, samples) in channel_levels.iter_mut().zip(planes) {
+            levels.push(samples);
+        }
+    }
+
+    let list = options.channel_names.iter().zip(channel_levels)
+        .map(|(name, level_data)| AnyChannel::new(name.as_str(), Levels::Mip { rounding_mode, level_data }))
+        .collect();
+
+    let layer = Layer::new(
+        size,
+        unwrap_or_return_err!(layer_attributes(&options, &options.layer_name)),
+        unwrap_or_return_err!(exr_encoding(&options)),
+        AnyChannels::sort(list),
+    );
+
+    unwrap_or_return_err!(WriteTarget::File(path_str).write(&Image::new(image_attributes(&options, size.0, size.1), layer), &options));
+    ExrStatus::Ok
+}
+
+/// Writes a tiled RGBA image together with its rip map, the levels reduced along each axis on its own, which suits
+/// textures sampled at very different rates horizontally and vertically. `rounding_mode` halves odd sizes like for
+/// `write_texture_mipmapped`. Rounding down, level (`x`, `y`) is `max(1, base_width >> x)` pixels wide and
+/// `max(1, base_height >> y)` high, reading as many interleaved samples of `format` from
+/// `level_datas[y * num_levels_x + x]`. Full chains are required along both axes, `num_levels_x` and `num_levels_y`
+/// being the number of halvings until the width and height are 1 plus one, e.g. 11 and 10 for 1024x512.
+/// Unless `level_lens` is null, it holds the number of samples of each level in the same order, which must match
+/// the sizes the rounding mode gives.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `level_datas` must hold `num_levels_x * num_levels_y` pointers
+/// to the pixels of each level and `level_lens` must be null or hold as many lengths.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_texture_ripmapped(
+    path: *const c_char, base_width: i32, base_height: i32, format: ExrPixelFormat, encoding: ExrEncoding,
+    rounding_mode: u32, num_levels_x: u32, num_levels_y: u32, level_datas: *const *const c_void, level_lens: *const usize
+) -> ExrStatus {
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+
+    if base_width <= 0 || base_height <= 0 {
+        set_last_error(format!("invalid image size {}x{}", base_width, base_height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let Some(rounding_mode) = exr_rounding_mode(rounding_mode) else {
+        set_last_error(format!("invalid rounding mode {}", rounding_mode));
+        return ExrStatus::InvalidArgument;
+    };
+
+    let size = Vec2(base_width as usize, base_height as usize);
+    let expected_levels = exr::meta::rip_map_indices(rounding_mode, size).last().map_or(Vec2(0, 0), |last| last + Vec2(1, 1));
+    if Vec2(num_levels_x as usize, num_levels_y as usize) != expected_levels {
+        set_last_error(format!(
+            "{}x{} needs {}x{} rip levels, got {}x{}",
+            base_width, base_height, expected_levels.x(), expected_levels.y(), num_levels_x, num_levels_y
+        ));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let level_sizes = exr::meta::rip_map_levels(rounding_mode, size).map(|(_, level_size)| level_size).collect::<Vec<_>>();
+    if !level_lens_match(level_lens, &level_sizes) {
+        set_last_error(format!("rip levels of {}x{} do not have the sizes rounding {:?} gives", base_width, base_height, rounding_mode));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let options = WriteOptions { encoding, block_mode: BLOCK_MODE_TILES, ..Default::default() };
+    let level_datas = from_raw_parts(level_datas, expected_levels.area());
+
+    // One list of levels per channel, in the order exr indexes rip maps by
+    let mut channel_levels: Vec<Vec<FlatSamples>> = vec![Vec::new(); 4];
+    for (level_size, data) in level_sizes.into_iter().zip(level_datas) {
+        let Some(planes) = deinterleave_planes(*data, level_size.area(), 4, format) else {
+            set_last_error("unsupported pixel format");
+            return ExrStatus::Unsupported;
+        };
+
+        for (levels, samples) in channel_levels.iter_mut().zip(planes) {
+            levels.push(samples);
+        }
+    }
+
+    let list = options.channel_names.iter().zip(channel_levels)
+        .map(|(name, map_data)| AnyChannel::new(name.as_str(), Levels::Rip {
+            rounding_mode,
+            level_data: RipMaps { map_data, level_count: expected_levels },
+        }))
+        .collect();
+
+    let layer = Layer::new(
+        size,
+        unwrap_or_return_err!(layer_attributes(&options, &options.layer_name)),
+        unwrap_or_return_err!(exr_encoding(&options)),
+        AnyChannels::sort(list),
+    );
+
+    unwrap_or_return_err!(WriteTarget::File(path_str).write(&Image::new(image_attributes(&options, size.0, size.1), layer), &options));
+    ExrStatus::Ok
+}
+
+/// Writes RGB or RGBA pixels as luminance and chroma, the layout OpenEXR offers to save space on color images.
+/// Every pixel keeps its luminance "Y" and alpha "A", while the chroma channels "RY" and "BY" are averaged over
+/// 2x2 pixels and stored at half the resolution, all of them as F16. Luminance is weighted for Rec. 709 primaries.
+/// `num_channels` is 3 for RGB or 4 for RGBA data of `format` F16 or F32. Both `width` and `height` must be even,
+/// and only the Uncompressed, RLE, ZIP1 and ZIP16 encodings are available for subsampled channels.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string and `data` must point to `width * height * num_channels` samples of `format`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn write_texture_luminance_chroma(
+    path: *const c_char, width: i32, height: i32, num_channels: u32, format: ExrPixelFormat, encoding: ExrEncoding,
+    data: *const c_void
+) -> ExrStatus {
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+
+    if width <= 0 || height <= 0 || width % 2 != 0 || height % 2 != 0 {
+        set_last_error(format!("chroma subsampling needs an even image size, got {}x{}", width, height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    if num_channels != 3 && num_channels != 4 {
+        set_last_error(format!("luminance and chroma are written from 3 or 4 channels, got {}", num_channels));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let compression = unwrap_or_return_err!(exr_encoding(&WriteOptions { encoding, ..Default::default() })).compression;
+    if !matches!(compression, Compression::Uncompressed | Compression::RLE | Compression::ZIP1 | Compression::ZIP16) {
+        set_last_error(format!("encoding {:?} cannot store subsampled channels", encoding));
+        return ExrStatus::Unsupported;
+    }
+
+    let (width, height, num_channels) = (width as usize, height as usize, num_channels as usize);
+    let len = width * height * num_channels;
+
+    let pixels = match format {
+        ExrPixelFormat::F16 => widen_f16(from_raw_parts(data as *const f16, len)),
+        ExrPixelFormat::F32 => from_raw_parts(data as *const f32, len).to_vec(),
+        _ => {
+            set_last_error("unsupported pixel format");
+            return ExrStatus::Unsupported;
+        }
+    };
+
+    unwrap_or_return_err!(exr::io::attempt_delete_file_on_write_error(Path::new(path_str), |file| {
+        write_luminance_chroma(BufWriter::new(file), Vec2(width, height), num_channels, compression, &pixels)
+    }));
+    ExrStatus::Ok
+}
+
+// Rec. 709 weights of red, green and blue in luminance
+const LUMINANCE_WEIGHTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+// exr refuses to write subsampled channels, so the file is put together here. Each scan line block holds the lines
+// of every channel in name order, skipping the lines and pixels a subsampled channel has no samples for.
+fn write_luminance_chroma(mut write: impl Write, size: Vec2<usize>, num_channels: usize, compression: Compression, pixels: &[f32]) -> Result<()> {
+    let Vec2(width, height) = size;
+
+    let luminance = pixels.chunks_exact(num_channels)
+        .map(|pixel| pixel.iter().zip(LUMINANCE_WEIGHTS).map(|(sample, weight)| sample * weight).sum::<f32>())
+        .collect::<Vec<_>>();
+
+    // The ratio of a color to luminance, minus one, averaged over 2x2 pixels
+    let chroma = |color: usize| -> Vec<f16> {
+        (0..height / 2).flat_map(|y| (0..width / 2).map(move |x| (y, x))).map(|(y, x)| {
+            let sum = [(0, 0), (1, 0), (0, 1), (1, 1)].iter().map(|(dx, dy)| {
+                let index = (y * 2 + dy) * width + x * 2 + dx;
+                let luminance = luminance[index];
+                if luminance > 0.0 { (pixels[index * num_channels + color] - luminance) / luminance } else { 0.0 }
+            }).sum::<f32>();
+
+            f16::from_f32(sum / 4.0)
+        }).collect()
+    };
+
+    // Sorted by name as the channel list requires, with their sampling rate
+    let mut planes = Vec::new();
+    if num_channels == 4 {
+        planes.push(("A", 1, pixels.iter().skip(3).step_by(4).map(|&alpha| f16::from_f32(alpha)).collect::<Vec<_>>()));
+    }
+    planes.push(("BY", 2, chroma(2)));
+    planes.push(("RY", 2, chroma(0)));
+    planes.push(("Y", 1, luminance.iter().map(|&luminance| f16::from_f32(luminance)).collect()));
+
+    let channels = planes.iter()
+        .map(|(name, sampling, _)| ChannelDescription {
+            name: Text::from(*name),
+            sample_type: SampleType::F16,
+            quantize_linearly: false,
+            sampling: Vec2(*sampling, *sampling),
+        })
+        .collect();
+
+    let header = Header::new(Text::from(DEFAULT_LAYER_NAME), size, channels)
+        .with_encoding(compression, BlockDescription::ScanLines, LineOrder::Increasing);
+
+    let lines_per_block = compression.scan_lines_per_block();
+    let chunks = (0..height).step_by(lines_per_block).map(|y| {
+        let lines = lines_per_block.min(height - y);
+        let mut bytes = Vec::new();
+
+        for line in y..y + lines {
+            for (_, sampling, samples) in &planes {
+                if line % sampling == 0 {
+                    let row_len = width / sampling;
+                    let row = line / sampling * row_len;
+                    samples[row..row + row_len].iter().for_each(|sample| bytes.extend_from_slice(&sample.to_ne_bytes()));
+                }
+            }
+        }
+
+        let section = IntegerBounds::new(Vec2(0, y as i32), Vec2(width, lines));
+        Ok((y, compression.compress_image_section_to_le(&header, bytes, section)?))
+    }).collect::<Result<Vec<_>>>()?;
+
+    let requirements = Requirements {
+        file_format_version: 2,
+        is_single_layer_and_tiled: false,
+        has_long_names: false,
+        has_deep_data: false,
+        has_multiple_layers: false,
+    };
+
+    let mut meta = Vec::new();
+    exr::meta::magic_number::write(&mut meta)?;
+    requirements.write(&mut meta)?;
+    Header::write_all(&[header], &mut meta, false)?;
+    write.write_all(&meta)?;
+
+    // The offset table points at each block, which starts with its y coordinate and byte count
+    let mut offset = meta.len() + chunks.len() * mem::size_of::<u64>();
+    for (_, bytes) in &chunks {
+        write.write_all(&(offset as u64).to_le_bytes())?;
+        offset += 2 * mem::size_of::<i32>() + bytes.len();
+    }
+
+    for (y, bytes) in &chunks {
+        write.write_all(&(*y as i32).to_le_bytes())?;
+        write.write_all(&(bytes.len() as i32).to_le_bytes())?;
+        write.write_all(bytes)?;
+    }
+
+    write.flush()?;
+    Ok(())
+}
+
+/// Re-encodes the EXR file at `src_path` with `encoding` and stores it at `dst_path`, e.g. to archive renders as
+/// DWAB. Unlike loading the pixels and writing them again, every layer, channel, sample type, mip or rip level and
+/// attribute is kept, as are the block layout and line order of each layer. Only the compression changes, and with
+/// it the precision of the samples for the lossy encodings. `dwa_compression_level` is only used by the DWA
+/// encodings, like for `write_texture`. The file is read completely before it is written, so `dst_path` may be
+/// `src_path`. Deep and subsampled files are not supported.
+///
+/// # Safety
+/// `src_path` and `dst_path` must be valid nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn transcode(
+    src_path: *const c_char, dst_path: *const c_char, encoding: ExrEncoding, dwa_compression_level: f32
+) -> ExrStatus {
+    let src_path = unwrap_or_return_err!(CStr::from_ptr(src_path).to_str());
+    let dst_path = unwrap_or_return_err!(CStr::from_ptr(dst_path).to_str());
+
+    let options = WriteOptions { encoding, dwa_compression_level, ..Default::default() };
+    let compression = unwrap_or_return_err!(exr_encoding(&options)).compression;
+
+    let mut image = unwrap_or_return_err!(
+        read().no_deep_data().all_resolution_levels().all_channels().all_layers().all_attributes().from_file(src_path)
+    );
+    for layer in image.layer_data.iter_mut() {
+        layer.encoding.compression = compression;
+    }
+
+    unwrap_or_return_err!(image.write().to_file(dst_path));
+    ExrStatus::Ok
+}
+
+/// Loads the image at `exr_path` like `load_from_path` and stores it as an 8 bit PNG thumbnail at `png_path`.
+/// Samples are scaled by `2^exposure`, encoded with a power of `1 / gamma`, or the sRGB curve for a `gamma` of 0,
+/// and clamped to 0..1. Alpha is only clamped. Single channel images become greyscale, images of two channels get a
+/// blue of 0 and channels past the fourth are dropped.
+///
+/// # Safety
+/// `exr_path` and `png_path` must be valid nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn write_png_preview(exr_path: *const c_char, png_path: *const c_char, exposure: f32, gamma: f32) -> ExrStatus {
+    let png_path_str = unwrap_or_return_err!(CStr::from_ptr(png_path).to_str());
+
+    if !exposure.is_finite() || !gamma.is_finite() || gamma < 0.0 {
+        set_last_error(format!("invalid exposure {} or gamma {}", exposure, gamma));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let (mut width, mut height, mut num_channels, mut format) = (0, 0, 0, ExrPixelFormat::Unknown);
+    let mut data = std::ptr::null_mut();
+    let options = ExrLoadOptions { sanitize: true, requested_format: ExrPixelFormat::F32, ..Default::default() };
+    let status = load_from_path(
+        exr_path, std::ptr::null(), &options, &mut width, &mut height, &mut num_channels, &mut format, std::ptr::null_mut(),
+        std::ptr::null_mut(), 0, std::ptr::null_mut(), &mut data
+    );
+    if status != ExrStatus::Ok {
+        return status;
+    }
+
+    let (width, height, num_channels) = (width as usize, height as usize, num_channels as usize);
+    let samples = from_raw_parts(data as *const f32, width * height * num_channels).to_vec();
+    free_texture(data, width as u32, height as u32, num_channels as u32, format);
+
+    let scale = exposure.exp2();
+    let encode = |sample: f32| {
+        let linear = (sample * scale).max(0.0);
+        let encoded = if gamma == 0.0 { linear_to_srgb(linear) } else { linear.powf(1.0 / gamma) };
+        to_unorm8(encoded)
+    };
+
+    let result = match num_channels {
+        1 => image::GrayImage::from_raw(width as u32, height as u32, samples.iter().map(|&sample| encode(sample)).collect())
+            .map(|image| image.save_with_format(png_path_str, image::ImageFormat::Png)),
+        _ => {
+            let pixels = samples.chunks_exact(num_channels).flat_map(|pixel| {
+                let channel = |index: usize| pixel.get(index).copied().unwrap_or(0.0);
+                let alpha = if num_channels >= 4 { to_unorm8(pixel[3]) } else { u8::MAX };
+                [encode(channel(0)), encode(channel(1)), encode(channel(2)), alpha]
+            }).collect();
+
+            image::RgbaImage::from_raw(width as u32, height as u32, pixels)
+                .map(|image| image.save_with_format(png_path_str, image::ImageFormat::Png))
+        }
+    };
+
+    match result {
+        Some(result) => {
+            unwrap_or_return_err!(result);
+            ExrStatus::Ok
+        },
+        None => {
+            set_last_error("pixel count");
+            ExrStatus::DecodeError
+        }
+    }
+}
+
+fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.0031308 { linear * 12.92 } else { 1.055 * linear.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn srgb_to_linear(encoded: f32) -> f32 {
+    if encoded <= 0.04045 { encoded / 12.92 } else { ((encoded + 0.055) / 1.055).powf(2.4) }
+}
+
+// Applies `transfer` to the color channels of `num_pixels` interleaved float pixels, which are the ones named R, G,
+// B or Y, also within a layer such as "diffuse.R". Other channels and integer samples are left alone.
+unsafe fn apply_transfer(pixels: *mut c_void, num_pixels: usize, format: ExrPixelFormat, channel_names: &str, transfer: fn(f32) -> f32) {
+    let names = channel_names.split('\n').collect::<Vec<_>>();
+    let colors = names.iter().enumerate()
+        .filter(|(_, name)| matches!(name.rsplit('.').next(), Some("R" | "G" | "B" | "Y")))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    let len = num_pixels * names.len();
+
+    match format {
+        ExrPixelFormat::F16 => for pixel in from_raw_parts_mut(pixels as *mut f16, len).chunks_exact_mut(names.len()) {
+            for &color in &colors {
+                pixel[color] = f16::from_f32(transfer(pixel[color].to_f32()));
+            }
+        },
+        ExrPixelFormat::F32 | ExrPixelFormat::RGBF32 => for pixel in from_raw_parts_mut(pixels as *mut f32, len).chunks_exact_mut(names.len()) {
+            for &color in &colors {
+                pixel[color] = transfer(pixel[color]);
+            }
+        },
+        _ => {}
+    }
+}
+
+// NaN ends up as 0
+fn to_unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Writes `width * height` RGB pixels of three f32 samples each as a Radiance RGBE ".hdr" file, the format
+/// `load_from_path` reads as well. Negative and NaN samples are stored as black and samples
+/// too bright for RGBE are clamped, as the format has no way to represent them.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string and `data` must point to `width * height * 3` samples.
+#[no_mangle]
+pub unsafe extern "C" fn write_hdr(path: *const c_char, width: i32, height: i32, data: *const f32) -> ExrStatus {
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+
+    if width <= 0 || height <= 0 {
+        set_last_error(format!("invalid image size {}x{}", width, height));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let data = from_raw_parts(data, width * height * 3);
+
+    unwrap_or_return_err!(exr::io::attempt_delete_file_on_write_error(Path::new(path_str), |file| {
+        Ok(write_rgbe(BufWriter::new(file), width, height, data)?)
+    }));
+    ExrStatus::Ok
+}
+
+// Scan lines of a width the run length encoding supports are encoded per component, others are stored flat
+fn write_rgbe(mut write: impl Write, width: usize, height: usize, data: &[f32]) -> std::io::Result<()> {
+    write!(write, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+
+    let mut components: [Vec<u8>; 4] = Default::default();
+    for line in data.chunks_exact(width * 3) {
+        let pixels = line.chunks_exact(3).map(|rgb| rgbe(rgb[0], rgb[1], rgb[2]));
+
+        if !(8..=0x7fff).contains(&width) {
+            for pixel in pixels {
+                write.write_all(&pixel)?;
+            }
+            continue;
+        }
+
+        components.iter_mut().for_each(Vec::clear);
+        for pixel in pixels {
+            for (component, value) in components.iter_mut().zip(pixel) {
+                component.push(value);
+            }
+        }
+
+        write.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+        for component in &components {
+            write_rgbe_runs(&mut write, component)?;
+        }
+    }
+
+    write.flush()
+}
+
+// Runs of at least three equal bytes become a count above 128 and the byte, everything else is copied
+// in chunks of up to 128 bytes after their count
+fn write_rgbe_runs(write: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    let mut start = 0;
+
+    while start < bytes.len() {
+        let run = bytes[start..].iter().take(127).take_while(|&&byte| byte == bytes[start]).count();
+        if run >= 3 {
+            write.write_all(&[128 + run as u8, bytes[start]])?;
+            start += run;
+            continue;
+        }
+
+        let mut end = start + 1;
+        while end < bytes.len() && end - start < 128 {
+            let next_run = bytes[end..].iter().take(3).take_while(|&&byte| byte == bytes[end]).count();
+            if next_run >= 3 {
+                break;
+            }
+            end += 1;
+        }
+
+        write.write_all(&[(end - start) as u8])?;
+        write.write_all(&bytes[start..end])?;
+        start = end;
+    }
+
+    Ok(())
+}
+
+// Shares the exponent of the brightest component, which always keeps a mantissa of at least 128
+fn rgbe(red: f32, green: f32, blue: f32) -> [u8; 4] {
+    // The largest value whose exponent still fits in a byte
+    const MAX: f32 = 255.0 / 256.0 * (1_u128 << 127) as f32;
+    let clamp = |value: f32| if value > 0.0 { value.min(MAX) } else { 0.0 };
+    let (red, green, blue) = (clamp(red), clamp(green), clamp(blue));
+
+    let max = red.max(green).max(blue);
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // `max` is `mantissa * 2^exponent` with a mantissa in [0.5, 1)
+    let exponent = ((max.to_bits() >> 23) & 0xff) as i32 - 126;
+    let scale = 2_f32.powi(8 - exponent);
+
+    [(red * scale) as u8, (green * scale) as u8, (blue * scale) as u8, (exponent + 128) as u8]
+}
+
+// Splits `pixel_count` interleaved pixels into one plane per channel
+unsafe fn deinterleave_planes(data: *const c_void, pixel_count: usize, num_channels: usize, format: ExrPixelFormat) -> Option<Vec<FlatSamples>> {
+    let len = pixel_count * num_channels;
+
+    match format {
+        ExrPixelFormat::U32 => Some(deinterleave(from_raw_parts(data as *const u32, len), num_channels).into_iter().map(FlatSamples::U32).collect()),
+        ExrPixelFormat::F16 => Some(deinterleave(from_raw_parts(data as *const f16, len), num_channels).into_iter().map(FlatSamples::F16).collect()),
+        ExrPixelFormat::F32 => Some(deinterleave(from_raw_parts(data as *const f32, len), num_channels).into_iter().map(FlatSamples::F32).collect()),
+        _ => None,
+    }
+}
+
+fn deinterleave<T: Copy>(data: &[T], num_channels: usize) -> Vec<Vec<T>> {
+    (0..num_channels)
+        .map(|channel| data.iter().skip(channel).step_by(num_channels).copied().collect())
+        .collect()
+}
+
+// Null and empty strings both mean "not set"
+unsafe fn read_optional_str(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+
+    let string = CStr::from_ptr(ptr).to_string_lossy();
+    if string.is_empty() { None } else { Some(string.into_owned()) }
+}
+
+unsafe fn read_str_array(names: *const *const c_char, count: usize) -> Option<Vec<String>> {
+    if names.is_null() {
+        return None;
+    }
+
+    let names = from_raw_parts(names, count);
+    Some(names.iter().map(|name| CStr::from_ptr(*name).to_string_lossy().into_owned()).collect())
+}
+
+// A single U32 channel is taken for an object or material ID pass rather than luminance
+fn default_channel_names(count: usize, format: ExrPixelFormat) -> Vec<String> {
+    let names: &[&str] = match count {
+        1 if format == ExrPixelFormat::U32 => &["id"],
+        1 => &["Y"],
+        2 => &["X", "Y"],
+        3 => &["R", "G", "B"],
+        4 => &["R", "G", "B", "A"],
+        _ => &[],
+    };
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// Returns the options `load_from_path` uses for a null `options`: the first layer with all of its channels, decoded
+/// leniently with the samples as they are stored and without measuring them.
+#[no_mangle]
+pub extern "C" fn default_load_options() -> ExrLoadOptions {
+    ExrLoadOptions::default()
+}
+
+/// Decodes the image at `path` as `options` asks, null taking `default_load_options()`, whose fields are described
+/// below along with the parameters. Unless `info` is null it receives the fields of `ExrLoadInfo` described
+/// below, which are left at their defaults when loading failed.
+/// The pixels are stored in `data`, which is owned by this library and must be released with `free_texture`,
+/// or null when loading failed.
+/// `layer_name` selects the layer to decode by name, null or empty meaning the first layer of the file.
+/// `unpremultiply` divides the color channels of F16 and F32 RGBA layers by their alpha, for pipelines working
+/// with straight alpha. Pixels with an alpha of zero and layers without an alpha channel are left as they are.
+/// `sanitize` replaces NaN samples with 0 and clamps infinite ones to the largest finite value of the returned
+/// type as they are decoded, for consumers such as GPU uploads that cannot cope with them. The samples are kept as
+/// stored otherwise.
+/// `flip_vertical` returns the bottom row of the image first, as OpenGL expects textures, instead of the top row.
+/// The reported data window is the same either way.
+/// `to_srgb` encodes the color channels of F16 and F32 samples with the sRGB curve for direct display, and
+/// `from_srgb` decodes display referred samples back to linear, which cannot both be set. Color channels are named
+/// R, G, B or Y, alone or within a layer such as "diffuse.R", so alpha, depth and the like are kept as they are. U32
+/// samples are never changed. The curve is applied after unpremultiplying and before `requested_format` converts.
+/// `memory_map` decodes EXR files straight from a memory mapping instead of reading them through a buffer, which
+/// saves copying very large plates. The file must not be changed while it loads. Where it cannot be mapped, it is
+/// read as usual.
+/// `pedantic` rejects EXR files deviating from the specification in any way, such as an unreadable attribute,
+/// duplicate layer names or bytes left over after the pixels, with `ExrStatus::DecodeError`, which suits checking
+/// deliveries. Otherwise slightly malformed files
+/// written by other tools are loaded as far as possible.
+/// `force_exr` decodes the file as EXR whatever it starts with or its extension is, leaving it to the EXR decoder to
+/// reject files that are not.
+/// `channel_mask` selects which of the channels an EXR layer would be returned with are read and interleaved,
+/// bit `i` keeping channel `i`, so 0b0111 keeps RGB and 0b1000 the alpha of an RGBA layer. 0 keeps all channels,
+/// a mask selecting none is rejected. Layers are only unpremultiplied with all four of their channels.
+/// `requested_format` converts the returned samples to U32, F16 or F32 whatever type the file stores, which is then
+/// reported in `format`. `Unknown` keeps the type of the file, as does F32 for RGB images of `ExrPixelFormat::RGBF32`.
+/// Layers storing only U32 channels, such as ID passes, are returned bit exact unless converted. Mixed with float
+/// channels they are returned as F32, which only holds integers up to 2^24 exactly.
+/// Radiance and Portable Float Map images are read as well, of `ExrPixelFormat::RGBF32` or, for greyscale PFM, a
+/// single F32 channel. The format is told by the first bytes of the file, so wrong extensions do no harm, and only
+/// files starting like none of them are taken for Radiance when ending in ".hdr" and for PFM when ending in ".pfm".
+/// Luminance and chroma layers, with "Y", "RY" and "BY" channels as written by `write_texture_luminance_chroma`,
+/// are turned back into RGBA at full resolution. Other layers with subsampled channels are upsampled channel by channel.
+/// Unless `channel_names` is null, it receives the names of the returned channels in order, joined by newlines,
+/// which must be released with `free_string`, or null when loading failed.
+/// `has_alpha` tells whether one of the returned channels is an alpha channel the file stores, named "A" alone or
+/// within a layer such as "diffuse.A". A fourth channel is not necessarily alpha, it could as well be depth, nor is
+/// the opaque alpha filled in for RGB layers. Radiance and PFM images never have alpha.
+/// `width` and `height` are the size of the data window, whose origin is reported in `data_window_x` and
+/// `data_window_y`. The first returned pixel is the one at that origin, not at (0, 0).
+/// `display_window` receives the intended frame of the image, in the same pixel space as the data window.
+/// A cropped render has a data window inside the display window, an overscan render one reaching outside of it.
+/// `has_chromaticities` tells whether the file describes its primaries and white point, which are then stored in
+/// `chromaticities`. Files without them are usually Rec. 709, but that is up to the caller to assume.
+/// `pixel_aspect_ratio` receives the width of a pixel divided by its height, 1.0 for square pixels.
+/// `channel_formats` receives the sample type each returned channel is stored with in the file, for up to
+/// `channel_formats_len` channels. Mixed layers are returned as F32, so this tells which channels were only F16 or
+/// U32. A filled in alpha channel and RGB computed from luminance and chroma are not stored at all and reported
+/// as `ExrPixelFormat::Unknown`.
+/// `is_uniform` tells whether all channels of the layer are stored with one sample type, as Radiance and PFM images
+/// always are. Otherwise the layer mixes sample types, so `channel_formats` rather than `format` tells how each
+/// returned channel is stored.
+/// `encoding` receives the compression of the layer, `ExrEncoding::Other` for files that are not EXR or use a
+/// compression without a variant of its own.
+/// `has_time_code` tells whether the file carries a "timeCode" attribute, which is then stored in `time_code`.
+/// `num_layers` receives how many layers, or parts, the file holds, of which only the selected one is decoded.
+/// Formats other than EXR always hold one.
+/// `luminance_stats` receives the smallest, largest and mean luminance of the returned pixels, measured after
+/// unpremultiplying and the sRGB transfer, which saves a caller such as an auto exposure a pass over the pixels of its own.
+/// It is Rec. 709 luminance for layers with "R", "G" and "B" channels, otherwise the "Y" channel or, lacking one, the first
+/// channel. Pixels whose luminance is not finite are left out, and all three are 0 if none is left. The pixels are
+/// only measured when `measure_luminance` is set, so loads that do not need it pay nothing.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string, `options` null or valid, and
+/// the out pointers must be writable, except for `channel_names`, `channel_formats` and `info` which may be null.
+/// `channel_formats` must otherwise hold `channel_formats_len` elements.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_from_path(
+    path: *const c_char, layer_name: *const c_char, options: *const ExrLoadOptions, width: *mut i32, height: *mut i32,
+    num_channels: *mut i32, format: *mut ExrPixelFormat, channel_names: *mut *mut c_char, channel_formats: *mut ExrPixelFormat,
+    channel_formats_len: u32, info: *mut ExrLoadInfo, data: *mut *mut c_void
+) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+    set_channel_names(channel_names, None);
+    set_channel_formats(channel_formats, channel_formats_len, &[]);
+    set_load_info(info, ExrLoadInfo::default());
+    *data = std::ptr::null_mut();
+
+    let ExrLoadOptions {
+        unpremultiply, sanitize, flip_vertical, to_srgb, from_srgb, memory_map, pedantic, force_exr, requested_format,
+        channel_mask, measure_luminance: measure,
+    } = options.as_ref().copied().unwrap_or_default();
+
+    if requested_format == ExrPixelFormat::RGBF32 {
+        set_last_error("RGBF32 cannot be requested, use F32".to_string());
+        return ExrStatus::InvalidArgument;
+    }
+
+    let transfer: Option<fn(f32) -> f32> = match (to_srgb, from_srgb) {
+        (true, true) => {
+            set_last_error("to_srgb and from_srgb exclude each other");
+            return ExrStatus::InvalidArgument;
+        },
+        (true, false) => Some(linear_to_srgb),
+        (false, true) => Some(srgb_to_linear),
+        (false, false) => None,
+    };
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let kind = if force_exr { FileKind::Exr } else { file_kind(path_str) };
+
+    if kind == FileKind::Radiance {
+        let mut image = unwrap_or_return_err!(radiant::load(unwrap_or_return_err!(open_radiance(path_str))));
+
+        if flip_vertical {
+            flip_rows(&mut image.data, image.width);
+        }
+
+        *width = image.width as i32;
+        *height = image.height as i32;
+        *num_channels = 3;
+        set_channel_names(channel_names, Some("R\nG\nB".to_string()));
+        set_channel_formats(channel_formats, channel_formats_len, &[ExrPixelFormat::F32; 3]);
+
+        let pixels = into_raw(image.data);
+        if let Some(transfer) = transfer {
+            apply_transfer(pixels, image.width * image.height, ExrPixelFormat::RGBF32, "R\nG\nB", transfer);
+        }
+
+        set_load_info(info, ExrLoadInfo {
+            display_window: ExrBounds { x: 0, y: 0, width: image.width as i32, height: image.height as i32 },
+            is_uniform: true,
+            num_layers: 1,
+            luminance_stats: if measure { measure_luminance(pixels, image.width * image.height, ExrPixelFormat::RGBF32, "R\nG\nB") } else { ExrLuminanceStats::default() },
+            ..Default::default()
+        });
+
+        (*data, *format) = convert_texture(pixels, image.width, image.height, 3, ExrPixelFormat::RGBF32, requested_format);
+        return ExrStatus::Ok;
+    }
+
+    if kind == FileKind::Pfm {
+        let file = unwrap_or_return_err!(File::open(path_str));
+        let mut image = unwrap_or_return_err!(load_pfm(BufReader::new(file)));
+
+        *width = image.width as i32;
+        *height = image.height as i32;
+        *num_channels = image.num_channels as i32;
+        let names = if image.num_channels == 3 { "R\nG\nB" } else { "Y" };
+        set_channel_names(channel_names, Some(names.to_string()));
+        set_channel_formats(channel_formats, channel_formats_len, &vec![ExrPixelFormat::F32; image.num_channels]);
+
+        if sanitize {
+            image.data.iter_mut().for_each(|sample| *sample = sample.to_finite());
+        }
+
+        if flip_vertical {
+            flip_rows(&mut image.data, image.width * image.num_channels);
+        }
+
+        let image_format = image.format();
+        let pixels = into_raw(image.data);
+        if let Some(transfer) = transfer {
+            apply_transfer(pixels, image.width * image.height, image_format, names, transfer);
+        }
+
+        set_load_info(info, ExrLoadInfo {
+            display_window: ExrBounds { x: 0, y: 0, width: image.width as i32, height: image.height as i32 },
+            is_uniform: true,
+            num_layers: 1,
+            luminance_stats: if measure { measure_luminance(pixels, image.width * image.height, image_format, names) } else { ExrLuminanceStats::default() },
+            ..Default::default()
+        });
+
+        (*data, *format) = convert_texture(pixels, image.width, image.height, image.num_channels, image_format, requested_format);
+        return ExrStatus::Ok;
+    }
+
+    let file_map = if memory_map { map_file(path_str) } else { None };
+    let source = file_map.as_deref().map_or(ReadSource::File(path_str), ReadSource::Memory);
+    let (meta, tables_start) = unwrap_or_return_err!(source.meta_data_and_tables(pedantic));
+    let layer_index = match layer_index(&meta.headers, read_optional_str(layer_name)) {
+        Ok(index) => index,
+        Err(status) => return status,
+    };
+    let header = &meta.headers[layer_index];
+
+    let layer = match is_subsampled(header) {
+        true => match retain_channels(unwrap_or_return_err!(source.load_subsampled(&meta, layer_index, tables_start)), header.layer_size, channel_mask) {
+            Some(layer) => {
+                if sanitize {
+                    sanitize_pixels(layer.pixels, header.layer_size.area() * layer.num_channels, layer.format);
+                }
+
+                if flip_vertical {
+                    let len = header.layer_size.area() * layer.num_channels;
+                    match layer.format.element_size() {
+                        2 => flip_rows(from_raw_parts_mut(layer.pixels as *mut u16, len), header.layer_size.width() * layer.num_channels),
+                        _ => flip_rows(from_raw_parts_mut(layer.pixels as *mut u32, len), header.layer_size.width() * layer.num_channels),
+                    }
+                }
+
+                layer
+            },
+            None => {
+                set_last_error(format!("channel mask {:#b} selects no channel", channel_mask));
+                return ExrStatus::InvalidArgument;
+            }
+        },
+        false => {
+            let order = select_channels(channel_order(&header.channels), channel_mask);
+            if order.is_empty() {
+                set_last_error(format!("channel mask {:#b} selects no channel", channel_mask));
+                return ExrStatus::InvalidArgument;
+            }
+
+            let hooks = ReadHooks { sanitize, flip_vertical, pedantic, ..Default::default() };
+            let (pixels, format) = unwrap_or_return_err!(load_exr_layer(source, layer_index, Vec2(0, 0), header, &order, hooks));
+
+            LoadedLayer {
+                pixels,
+                format,
+                num_channels: order.len(),
+                channel_names: ordered_channel_names(&header.channels, &order),
+                channel_formats: ordered_channel_formats(&header.channels, &order),
+                rgba: is_rgba(&header.channels, &order),
+                num_layers: meta.headers.len(),
+            }
+        }
+    };
+
+    if unpremultiply && layer.rgba {
+        let len = header.layer_size.area() * 4;
+        match layer.format {
+            ExrPixelFormat::F16 => unpremultiply_alpha(from_raw_parts_mut(layer.pixels as *mut f16, len)),
+            ExrPixelFormat::F32 => unpremultiply_alpha(from_raw_parts_mut(layer.pixels as *mut f32, len)),
+            _ => {}
+        }
+    }
+
+    if let Some(transfer) = transfer {
+        apply_transfer(layer.pixels, header.layer_size.area(), layer.format, &layer.channel_names, transfer);
+    }
+
+    let position = header.own_attributes.layer_position;
+    let attributes = &header.shared_attributes;
+    set_load_info(info, ExrLoadInfo {
+        has_alpha: is_stored_alpha(&layer.channel_names, &layer.channel_formats),
+        data_window_x: position.x(),
+        data_window_y: position.y(),
+        display_window: attributes.display_window.into(),
+        has_chromaticities: attributes.chromaticities.is_some(),
+        chromaticities: attributes.chromaticities.map(ExrChromaticities::from).unwrap_or_default(),
+        pixel_aspect_ratio: attributes.pixel_aspect,
+        is_uniform: header.channels.uniform_sample_type.is_some(),
+        encoding: header.compression.into(),
+        has_time_code: attributes.time_code.is_some(),
+        time_code: attributes.time_code.map(ExrTimeCode::from).unwrap_or_default(),
+        num_layers: layer.num_layers as i32,
+        luminance_stats: if measure { measure_luminance(layer.pixels, header.layer_size.area(), layer.format, &layer.channel_names) } else { ExrLuminanceStats::default() },
+    });
+
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *num_channels = layer.num_channels as i32;
+    set_channel_names(channel_names, Some(layer.channel_names));
+    set_channel_formats(channel_formats, channel_formats_len, &layer.channel_formats);
+
+    (*data, *format) = convert_texture(
+        layer.pixels, header.layer_size.width(), header.layer_size.height(), layer.num_channels, layer.format, requested_format
+    );
+
+    ExrStatus::Ok
+}
+
+/// Decodes the `count` frames of an image sequence in parallel, frame `i` being read from `paths[i]` like
+/// `load_from_path` would, from the layer called `layer_name`, validated as `pedantic` asks and converted to
+/// `requested_format`. Its size, channel count and sample type are stored at index `i` of `widths`, `heights`,
+/// `num_channels` and `formats`, its status in `statuses` and its pixels in `data`, each to be released with
+/// `free_texture`.
+/// A sequence is expected to keep the layout of its first frame, so frames differing from it in size, channels or
+/// sample type are rejected with `ExrStatus::InvalidArgument`. Failed frames are left null without stopping the
+/// others, and the status of the first of them is returned, its message being kept for `get_last_error`.
+///
+/// # Safety
+/// `paths` must hold `count` valid nul-terminated strings, `layer_name` must be null or a valid string, and the
+/// out arrays must be writable for `count` elements. All of them may be null when `count` is 0.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_sequence(
+    paths: *const *const c_char, count: u32, layer_name: *const c_char, pedantic: bool, requested_format: ExrPixelFormat,
+    widths: *mut i32, heights: *mut i32, num_channels: *mut i32, formats: *mut ExrPixelFormat, statuses: *mut ExrStatus,
+    data: *mut *mut c_void
+) -> ExrStatus {
+    let count = count as usize;
+    if count == 0 {
+        return ExrStatus::Ok;
+    }
+
+    let outputs = (
+        from_raw_parts_mut(widths, count), from_raw_parts_mut(heights, count), from_raw_parts_mut(num_channels, count),
+        from_raw_parts_mut(formats, count), from_raw_parts_mut(statuses, count), from_raw_parts_mut(data, count),
+    );
+
+    if requested_format == ExrPixelFormat::RGBF32 {
+        set_last_error("RGBF32 cannot be requested, use F32");
+        outputs.4.fill(ExrStatus::InvalidArgument);
+        return ExrStatus::InvalidArgument;
+    }
+
+    let paths = from_raw_parts(paths, count).iter().map(|&path| CStr::from_ptr(path)).collect::<Vec<_>>();
+    let layer_name = (!layer_name.is_null()).then(|| CStr::from_ptr(layer_name));
+
+    let options = ExrLoadOptions { pedantic, requested_format, ..Default::default() };
+    let mut frames = paths.into_par_iter().map(|path| {
+        let mut frame = LoadedFrame::default();
+        frame.status = load_from_path(
+            path.as_ptr(), layer_name.map_or(std::ptr::null(), CStr::as_ptr), &options, &mut frame.width, &mut frame.height,
+            &mut frame.num_channels, &mut frame.format, std::ptr::null_mut(), std::ptr::null_mut(), 0, std::ptr::null_mut(),
+            &mut frame.data
+        );
+
+        if frame.status != ExrStatus::Ok {
+            frame.error = LAST_ERROR.with(|last_error| last_error.borrow().clone());
+        }
+
+        frame
+    }).collect::<Vec<_>>();
+
+    let layout = frames.iter()
+        .find(|frame| frame.status == ExrStatus::Ok)
+        .map(|frame| (frame.width, frame.height, frame.num_channels, frame.format));
+
+    for frame in frames.iter_mut().filter(|frame| frame.status == ExrStatus::Ok) {
+        if Some((frame.width, frame.height, frame.num_channels, frame.format)) != layout {
+            free_texture(frame.data, frame.width as u32, frame.height as u32, frame.num_channels as u32, frame.format);
+            frame.data = std::ptr::null_mut();
+            frame.status = ExrStatus::InvalidArgument;
+            frame.error = format!(
+                "frame of {}x{} with {} {:?} channels differs from the first one", frame.width, frame.height,
+                frame.num_channels, frame.format
+            );
+        }
+    }
+
+    let mut result = ExrStatus::Ok;
+    for (index, frame) in frames.into_iter().enumerate() {
+        if frame.status != ExrStatus::Ok && result == ExrStatus::Ok {
+            set_last_error(&frame.error);
+            result = frame.status;
+        }
+
+        outputs.0[index] = frame.width;
+        outputs.1[index] = frame.height;
+        outputs.2[index] = frame.num_channels;
+        outputs.3[index] = frame.format;
+        outputs.4[index] = frame.status;
+        outputs.5[index] = frame.data;
+    }
+
+    result
+}
+
+// A frame of `load_sequence` as decoded by one of the rayon threads
+struct LoadedFrame {
+    status: ExrStatus,
+    width: i32,
+    height: i32,
+    num_channels: i32,
+    format: ExrPixelFormat,
+    data: *mut c_void,
+    error: String,
+}
+
+// The pixels are owned by the frame alone until they are handed to the caller
+unsafe impl Send for LoadedFrame {}
+
+impl Default for LoadedFrame {
+    fn default() -> Self {
+        LoadedFrame {
+            status: ExrStatus::Ok,
+            width: -1,
+            height: -1,
+            num_channels: -1,
+            format: ExrPixelFormat::Unknown,
+            data: std::ptr::null_mut(),
+            error: String::new(),
+        }
+    }
+}
+
+/// Decodes the first layer of the EXR file held in the `len` bytes at `data`, e.g. as received over the
+/// network. The pixels are stored in `out_data`, ordered like `load_from_path` would, and must be released
+/// with `free_texture`. On failure `out_data` is null. `pedantic` validates the file like it does for `load_from_path`.
+///
+/// # Safety
+/// `data` must be valid for `len` bytes and the out pointers must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn load_from_memory(
+    data: *const u8, len: usize, pedantic: bool, width: *mut i32, height: *mut i32, num_channels: *mut i32,
+    format: *mut ExrPixelFormat, out_data: *mut *mut c_void
+) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+    *out_data = std::ptr::null_mut();
+
+    if data.is_null() {
+        set_last_error("data is null");
+        return ExrStatus::InvalidArgument;
+    }
+
+    let source = ReadSource::Memory(from_raw_parts(data, len));
+    let (meta, layer_index) = match find_layer(source, None, pedantic) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+    let header = &meta.headers[layer_index];
+    let order = channel_order(&header.channels);
+    let hooks = ReadHooks { pedantic, ..Default::default() };
+    let (pixels, exr_format) = unwrap_or_return_err!(load_exr_layer(source, layer_index, Vec2(0, 0), header, &order, hooks));
+
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *num_channels = order.len() as i32;
+    *format = exr_format;
+    *out_data = pixels;
+
+    ExrStatus::Ok
+}
+
+/// Decodes only mip level `level` of the layer called `layer_name`, null or empty meaning the first layer.
+/// Level 0 is the full resolution and every further level halves it, so `width` and `height` receive the size of
+/// the level rather than of the image. Files without mip maps only have level 0, other levels are rejected with
+/// `ExrStatus::InvalidArgument`. The pixels are ordered like `load_from_path` would and stored in `data`, which
+/// must be released with `free_texture`. `pedantic` validates the file like it does for `load_from_path`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string, and the out pointers must
+/// be writable.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_mip_level(
+    path: *const c_char, layer_name: *const c_char, level: u32, pedantic: bool, width: *mut i32, height: *mut i32,
+    num_channels: *mut i32, format: *mut ExrPixelFormat, data: *mut *mut c_void
+) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+    *data = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let source = ReadSource::File(path_str);
+    let (meta, layer_index) = match find_layer(source, read_optional_str(layer_name), pedantic) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+    let header = &meta.headers[layer_index];
+    let level = Vec2(level as usize, level as usize);
+
+    let Some(size) = level_size(header, level) else {
+        set_last_error(format!("no mip level {}", level.0));
+        return ExrStatus::InvalidArgument;
+    };
+
+    let order = channel_order(&header.channels);
+    let hooks = ReadHooks { pedantic, ..Default::default() };
+    let (pixels, exr_format) = unwrap_or_return_err!(load_exr_layer(source, layer_index, level, header, &order, hooks));
+
+    *width = size.width() as i32;
+    *height = size.height() as i32;
+    *num_channels = order.len() as i32;
+    *format = exr_format;
+    *data = pixels;
+
+    ExrStatus::Ok
+}
+
+/// Decodes the first layer holding channels that match `channel_pattern`, for files whose renderers order their
+/// layers differently. The pattern lists channel names separated by commas, each of which must match a channel of
+/// the layer, such as "Z" for the layer with depth or "R,G,B,A" for an RGBA beauty layer. `*` in a name matches any
+/// run of characters and `?` any single one, so "*.Z" finds depth stored within a named layer. Files without such a
+/// layer are rejected with `ExrStatus::InvalidArgument`. `layer_index` receives the index of the layer that was
+/// decoded, and the pixels are ordered and stored like `load_mip_level` does for level 0. `pedantic` validates the
+/// file like it does for `load_from_path`.
+///
+/// # Safety
+/// `path` and `channel_pattern` must be valid nul-terminated strings and the out pointers must be writable, except
+/// for `layer_index` and `channel_names` which may be null.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_layer_matching(
+    path: *const c_char, channel_pattern: *const c_char, pedantic: bool, layer_index: *mut i32, width: *mut i32,
+    height: *mut i32, num_channels: *mut i32, format: *mut ExrPixelFormat, channel_names: *mut *mut c_char, data: *mut *mut c_void
+) -> ExrStatus {
+    set_layer_index(layer_index, -1);
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+    set_channel_names(channel_names, None);
+    *data = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let channel_pattern = unwrap_or_return_err!(CStr::from_ptr(channel_pattern).to_str());
+    let source = ReadSource::File(path_str);
+    let meta = unwrap_or_return_err!(source.meta_data(pedantic));
+
+    let patterns = channel_pattern.split(',').map(str::trim).collect::<Vec<_>>();
+    let Some(index) = meta.headers.iter().position(|header| patterns.iter().all(|pattern| {
+        header.channels.list.iter().any(|channel| matches_pattern(pattern, &channel.name.to_string()))
+    })) else {
+        set_last_error(format!("no layer has channels matching \"{}\"", channel_pattern));
+        return ExrStatus::InvalidArgument;
+    };
+
+    let header = &meta.headers[index];
+    let order = channel_order(&header.channels);
+    let hooks = ReadHooks { pedantic, ..Default::default() };
+    let (pixels, exr_format) = unwrap_or_return_err!(load_exr_layer(source, index, Vec2(0, 0), header, &order, hooks));
+
+    set_layer_index(layer_index, index as i32);
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *num_channels = order.len() as i32;
+    *format = exr_format;
+    set_channel_names(channel_names, Some(ordered_channel_names(&header.channels, &order)));
+    *data = pixels;
+
+    ExrStatus::Ok
+}
+
+/// Decodes part `part_index` of a multi-part EXR file, such as one written by `write_multilayer`. Unlike the layers
+/// of a single part, whose channel names share a prefix like "diffuse.R", parts have headers of their own and may
+/// differ in size, channels and compression. `num_parts` receives how many parts the file holds and `part_names` their
+/// names in order, joined by newlines, an unnamed part of a single-part file being an empty line, to be released with
+/// `free_string`. Both are filled in as soon as the headers are read, so an index past the last part, which is
+/// rejected with `ExrStatus::InvalidArgument`, still reports them. The pixels are ordered and stored like
+/// `load_mip_level` does for level 0. `pedantic` validates the file like it does for `load_from_path`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string and the out pointers must be writable, except for `num_parts`,
+/// `part_names` and `channel_names` which may be null.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_part(
+    path: *const c_char, part_index: u32, pedantic: bool, num_parts: *mut i32, part_names: *mut *mut c_char, width: *mut i32,
+    height: *mut i32, num_channels: *mut i32, format: *mut ExrPixelFormat, channel_names: *mut *mut c_char, data: *mut *mut c_void
+) -> ExrStatus {
+    set_num_layers(num_parts, 0);
+    set_channel_names(part_names, None);
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+    set_channel_names(channel_names, None);
+    *data = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let source = ReadSource::File(path_str);
+    let meta = unwrap_or_return_err!(source.meta_data(pedantic));
+
+    let names = meta.headers.iter()
+        .map(|header| header.own_attributes.layer_name.as_ref().map_or_else(String::new, Text::to_string))
+        .collect::<Vec<_>>();
+    set_num_layers(num_parts, meta.headers.len());
+    set_channel_names(part_names, Some(names.join("\n")));
+
+    let index = part_index as usize;
+    let Some(header) = meta.headers.get(index) else {
+        set_last_error(format!("no part {} in a file of {}", index, meta.headers.len()));
+        return ExrStatus::InvalidArgument;
+    };
+
+    let order = channel_order(&header.channels);
+    let hooks = ReadHooks { pedantic, ..Default::default() };
+    let (pixels, exr_format) = unwrap_or_return_err!(load_exr_layer(source, index, Vec2(0, 0), header, &order, hooks));
+
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *num_channels = order.len() as i32;
+    *format = exr_format;
+    set_channel_names(channel_names, Some(ordered_channel_names(&header.channels, &order)));
+    *data = pixels;
+
+    ExrStatus::Ok
+}
+
+unsafe fn set_layer_index(layer_index: *mut i32, value: i32) {
+    if !layer_index.is_null() {
+        *layer_index = value;
+    }
+}
+
+// Whether `name` matches `pattern` as a whole, `*` standing for any run of characters and `?` for any one
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.chars().collect::<Vec<_>>(), name.chars().collect::<Vec<_>>());
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` when the characters following it stop matching
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    n = start + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Decodes only the pixels of the layer called `layer_name`, null or empty meaning the first layer, within the
+/// rectangle of `width` by `height` pixels at (`x`, `y`), e.g. the part of a huge plate a viewer shows. Like the data
+/// window, the rectangle is in the pixel space of the display window, and it is clamped to the data window first.
+/// `region` receives the rectangle that was decoded and `data` its `region.width * region.height` pixels, ordered
+/// like `load_from_path` would and to be released with `free_texture`. Only the tiles or scan line blocks touching
+/// the rectangle are read, so regions of tiled files are the cheapest to load. A rectangle missing the data window
+/// is rejected with `ExrStatus::InvalidArgument`. `pedantic` validates the file like it does for `load_from_path`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string, and the out pointers must
+/// be writable.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_region(
+    path: *const c_char, layer_name: *const c_char, x: i32, y: i32, width: i32, height: i32, pedantic: bool,
+    region: *mut ExrBounds, num_channels: *mut i32, format: *mut ExrPixelFormat, data: *mut *mut c_void
+) -> ExrStatus {
+    *region = ExrBounds::default();
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+    *data = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let source = ReadSource::File(path_str);
+    let (meta, layer_index) = match find_layer(source, read_optional_str(layer_name), pedantic) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+    let header = &meta.headers[layer_index];
+
+    let position = header.own_attributes.layer_position;
+    let (left, top) = (x.max(position.x()), y.max(position.y()));
+    let right = (x as i64 + width.max(0) as i64).min(position.x() as i64 + header.layer_size.width() as i64);
+    let bottom = (y as i64 + height.max(0) as i64).min(position.y() as i64 + header.layer_size.height() as i64);
+    if right <= left as i64 || bottom <= top as i64 {
+        set_last_error(format!("{}x{} at ({}, {}) is outside of the data window", width, height, x, y));
+        return ExrStatus::InvalidArgument;
+    }
+
+    let origin = Vec2((left - position.x()) as usize, (top - position.y()) as usize);
+    let extent = Vec2((right - left as i64) as usize, (bottom - top as i64) as usize);
+
+    let order = channel_order(&header.channels);
+    let hooks = ReadHooks { region: Some((origin, extent)), pedantic, ..Default::default() };
+    let (pixels, exr_format) = unwrap_or_return_err!(load_exr_layer(source, layer_index, Vec2(0, 0), header, &order, hooks));
+
+    *region = ExrBounds { x: left, y: top, width: extent.width() as i32, height: extent.height() as i32 };
+    *num_channels = order.len() as i32;
+    *format = exr_format;
+    *data = pixels;
+
+    ExrStatus::Ok
+}
+
+/// Decodes the deep scan line layer called `layer_name` of an EXR file, null meaning the first layer, into flat
+/// RGBA pixels of `ExrPixelFormat::F32`. `data` holds `width * height * 4` samples afterwards, to be released with
+/// `free_texture`, or null when loading failed.
+/// Each pixel is flattened to its front-most sample, the one with the smallest "Z", or the first stored sample of
+/// layers without depth. The samples behind it are dropped rather than composited, which keeps deep files
+/// viewable but loses any transparency between the samples. Pixels without samples are transparent black, color
+/// channels missing from the layer read as 0 and a missing alpha channel as 1.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string, and the out pointers must be
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn load_deep_flattened(
+    path: *const c_char, layer_name: *const c_char, width: *mut i32, height: *mut i32, data: *mut *mut c_void
+) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *data = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let layer_name = read_optional_str(layer_name);
+    let Some((header, pixels)) = unwrap_or_return_err!(flatten_deep_layer(path_str, layer_name.as_deref())) else {
+        set_last_error(format!("no layer named \"{}\"", layer_name.unwrap_or_default()));
+        return ExrStatus::InvalidArgument;
+    };
+
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *data = into_raw(pixels);
+
+    ExrStatus::Ok
+}
+
+/// Decodes the layer called `layer_name` of an EXR file like `load_mip_level` does for level 0, calling `progress`
+/// with the decoded fraction of the blocks along the way, first with 0.0 and last with exactly 1.0. The calls may
+/// come from the decoding threads, but never at the same time. `progress` may be null.
+/// Setting the flag `cancel` points to stops decoding with `ExrStatus::Aborted`, leaving `data` null. `cancel` may be
+/// null as well. `pedantic` validates the file like it does for `load_from_path`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string, `cancel` null or valid until
+/// this returns, and the out pointers must be writable.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_with_progress(
+    path: *const c_char, layer_name: *const c_char, pedantic: bool, width: *mut i32, height: *mut i32, num_channels: *mut i32,
+    format: *mut ExrPixelFormat, progress: Option<ExrProgressCallback>, cancel: *const AtomicBool, data: *mut *mut c_void
+) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+    *data = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let source = ReadSource::File(path_str);
+    let (meta, layer_index) = match find_layer(source, read_optional_str(layer_name), pedantic) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+    let header = &meta.headers[layer_index];
+    let order = channel_order(&header.channels);
+    let hooks = ReadHooks { progress, cancel: cancel.as_ref(), pedantic, ..Default::default() };
+    let (pixels, exr_format) = unwrap_or_return_err!(load_exr_layer(source, layer_index, Vec2(0, 0), header, &order, hooks));
+
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *num_channels = order.len() as i32;
+    *format = exr_format;
+    *data = pixels;
+
+    ExrStatus::Ok
+}
+
+/// Decodes what can be read of the layer called `layer_name` of an EXR file that may be truncated, such as one left
+/// behind by a render that crashed while writing it, like `load_mip_level` does for level 0. An offset table that was
+/// never filled in is rebuilt from the blocks that made it into the file. Blocks that are missing or cannot be
+/// decoded are filled with `default_pixel` instead, channel `i` of a pixel taking `default_pixel[i]` and channels past
+/// the fourth `default_pixel[3]`, null meaning 0.
+/// `missing_rows` receives how many rows were not decoded completely, in which case `ExrStatus::PartialData` is
+/// returned. The pixels are stored in `data` all the same and must be released with `free_texture` either way.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string, `default_pixel` null or
+/// valid, and the out pointers must be writable.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_partial(
+    path: *const c_char, layer_name: *const c_char, default_pixel: *const [f32; 4], width: *mut i32, height: *mut i32,
+    num_channels: *mut i32, format: *mut ExrPixelFormat, missing_rows: *mut i32, data: *mut *mut c_void
+) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+    *missing_rows = 0;
+    *data = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let source = ReadSource::File(path_str);
+    let (meta, layer_index) = match find_layer(source, read_optional_str(layer_name), false) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+    let header = &meta.headers[layer_index];
+    let order = channel_order(&header.channels);
+    let missing = AtomicUsize::new(0);
+    let hooks = ReadHooks {
+        missing_rows: Some(&missing),
+        default_pixel: default_pixel.as_ref().copied().unwrap_or_default(),
+        ..Default::default()
+    };
+    let (pixels, exr_format) = unwrap_or_return_err!(load_exr_layer(source, layer_index, Vec2(0, 0), header, &order, hooks));
+
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *num_channels = order.len() as i32;
+    *format = exr_format;
+    *missing_rows = missing.load(Ordering::Relaxed) as i32;
+    *data = pixels;
+
+    if *missing_rows > 0 {
+        set_last_error(format!("{} of {} rows could not be decoded", *missing_rows, *height));
+        return ExrStatus::PartialData;
+    }
+
+    ExrStatus::Ok
+}
+
+/// Releases a buffer returned by `load_from_path`, `load_sequence`, `load_from_memory`, `load_mip_level`,
+/// `load_layer_matching`, `load_part`, `load_region`, `load_deep_flattened`, `load_with_progress` or `load_partial`.
+///
+/// # Safety
+/// `data` must come from one of the loaders above and the remaining arguments must match what it reported.
+#[no_mangle]
+pub unsafe extern "C" fn free_texture(data: *mut c_void, width: u32, height: u32, num_channels: u32, format: ExrPixelFormat) {
+    if data.is_null() {
+        return;
+    }
+
+    let len = width as usize * height as usize * num_channels as usize;
+
+    match format {
+        ExrPixelFormat::U32 => drop(Vec::from_raw_parts(data as *mut u32, len, len)),
+        ExrPixelFormat::F16 => drop(Vec::from_raw_parts(data as *mut f16, len, len)),
+        ExrPixelFormat::F32 | ExrPixelFormat::RGBF32 => drop(Vec::from_raw_parts(data as *mut f32, len, len)),
+        ExrPixelFormat::Unknown => {}
+    }
+}
+
+/// Checks that `path` is an EXR file and reads the size of its first layer from the headers, without decoding
+/// any pixels. `num_channels` is the channel count `load_from_path` would return, i.e. 4 for RGB layers.
+/// Files that do not start like an EXR are rejected with `ExrStatus::NotExr`, EXR files with broken headers with
+/// `ExrStatus::DecodeError`. The out parameters are -1 unless this returns `ExrStatus::Ok`.
+/// `block_mode` receives how the layer is stored, 1 meaning scan lines and 2 tiles like the `block_mode` of
+/// `write_texture`. `level_mode` is 0 for a single resolution, 1 for mip maps and 2 for rip maps, which are
+/// always tiled. `deep` tells whether the layer holds deep samples, which only `load_deep_flattened` decodes.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string and the out pointers must be writable, except for `block_mode`,
+/// `level_mode` and `deep` which may be null.
+#[no_mangle]
+pub unsafe extern "C" fn probe(
+    path: *const c_char, width: *mut i32, height: *mut i32, num_channels: *mut i32, block_mode: *mut i32,
+    level_mode: *mut i32, deep: *mut bool
+) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    set_storage(block_mode, level_mode, deep, None);
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let mut file = unwrap_or_return_err!(File::open(path_str));
+
+    let mut magic_number = [0; 4];
+    if file.read_exact(&mut magic_number).is_err() || magic_number != exr::meta::magic_number::BYTES {
+        set_last_error(format!("{} is not an EXR file", path_str));
+        return ExrStatus::NotExr;
+    }
+
+    let (meta, layer_index) = match find_layer(ReadSource::File(path_str), None, false) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+    let header = &meta.headers[layer_index];
+
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *num_channels = channel_order(&header.channels).len() as i32;
+    set_storage(block_mode, level_mode, deep, Some(header));
+
+    ExrStatus::Ok
+}
+
+/// Reads the 8 bit thumbnail of the "preview" attribute, as embedded by the `preview_size` of `write_texture`, from
+/// the headers alone, which keeps thumbnailing a folder of frames fast however large they are. Multi-part files give
+/// the preview of the first part that has one. `rgba` receives `width * height` pixels of red, green, blue and alpha,
+/// top row first, to be released with `free_preview`. Files without a preview are rejected with `ExrStatus::NoPreview`,
+/// files that are not EXR with `ExrStatus::NotExr`. The out parameters are -1 and null unless this returns `ExrStatus::Ok`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string and the out pointers must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn read_preview(path: *const c_char, width: *mut i32, height: *mut i32, rgba: *mut *mut u8) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *rgba = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let mut file = unwrap_or_return_err!(File::open(path_str));
+
+    let mut magic_number = [0; 4];
+    if file.read_exact(&mut magic_number).is_err() || magic_number != exr::meta::magic_number::BYTES {
+        set_last_error(format!("{} is not an EXR file", path_str));
+        return ExrStatus::NotExr;
+    }
+
+    let meta = unwrap_or_return_err!(ReadSource::File(path_str).meta_data(false));
+    let Some(preview) = meta.headers.iter().find_map(|header| header.own_attributes.preview.as_ref()) else {
+        set_last_error(format!("{} has no preview", path_str));
+        return ExrStatus::NoPreview;
+    };
+
+    if preview.pixel_data.len() != preview.size.area() * 4 {
+        set_last_error("preview size");
+        return ExrStatus::DecodeError;
+    }
+
+    *width = preview.size.width() as i32;
+    *height = preview.size.height() as i32;
+    *rgba = into_raw(preview.pixel_data.iter().map(|&value| value as u8).collect::<Vec<_>>()) as *mut u8;
+
+    ExrStatus::Ok
+}
+
+/// Releases a preview returned by `read_preview`.
+///
+/// # Safety
+/// `rgba` must be null or come from `read_preview` together with its `width` and `height`.
+#[no_mangle]
+pub unsafe extern "C" fn free_preview(rgba: *mut u8, width: u32, height: u32) {
+    if !rgba.is_null() {
+        let len = width as usize * height as usize * 4;
+        drop(Vec::from_raw_parts(rgba, len, len));
+    }
+}
+
+unsafe fn set_storage(block_mode: *mut i32, level_mode: *mut i32, deep: *mut bool, header: Option<&Header>) {
+    if !block_mode.is_null() {
+        *block_mode = header.map_or(-1, |header| exr_block_mode(header.blocks) as i32);
+    }
+
+    if !level_mode.is_null() {
+        *level_mode = header.map_or(-1, |header| exr_level_mode(header.blocks) as i32);
+    }
+
+    if !deep.is_null() {
+        *deep = header.is_some_and(|header| header.deep);
+    }
+}
+
+fn exr_block_mode(blocks: BlockDescription) -> u32 {
+    match blocks {
+        BlockDescription::ScanLines => BLOCK_MODE_SCAN_LINES,
+        BlockDescription::Tiles(_) => BLOCK_MODE_TILES,
+    }
+}
+
+fn exr_level_mode(blocks: BlockDescription) -> u32 {
+    match blocks {
+        BlockDescription::Tiles(tiles) if tiles.level_mode == LevelMode::MipMap => LEVEL_MODE_MIP_MAP,
+        BlockDescription::Tiles(tiles) if tiles.level_mode == LevelMode::RipMap => LEVEL_MODE_RIP_MAP,
+        _ => LEVEL_MODE_SINGULAR,
+    }
+}
+
+/// Reads only the headers of `path` and describes every layer as JSON in `out_json`, e.g.
+/// `{"layers":[{"name":"beauty","width":1920,"height":1080,"encoding":"PIZ","blocks":"Tiles","levels":"MipMap","deep":false,"channels":[{"name":"R","sample_type":"F16"}],"chromaticities":null}]}`.
+/// `encoding` names the `ExrEncoding` of the layer. `blocks` is either "ScanLines" or "Tiles" and `levels` one of
+/// "Singular", "MipMap" and "RipMap". `chromaticities` is either null or the red, green, blue and white xy coordinates as eight numbers.
+/// The string must be released with `free_string`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string and `out_json` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn read_info(path: *const c_char, out_json: *mut *mut c_char) -> ExrStatus {
+    *out_json = std::ptr::null_mut();
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+
+    match MetaData::read_from_file(path_str, false) {
+        Ok(meta) => {
+            let layers = meta.headers.iter().map(layer_info_json).collect::<Vec<_>>();
+            *out_json = into_c_string(format!("{{\"layers\":[{}]}}", layers.join(",")));
+            ExrStatus::Ok
+        },
+        Err(e) => {
+            set_last_error(&e);
+            ExrStatus::from(&e)
+        }
+    }
+}
+
+fn layer_info_json(header: &Header) -> String {
+    let name = match &header.own_attributes.layer_name {
+        Some(name) => json_string(&name.to_string()),
+        None => "null".to_string(),
+    };
+
+    let channels = header.channels.list.iter()
+        .map(|channel| format!(
+            "{{\"name\":{},\"sample_type\":\"{:?}\"}}",
+            json_string(&channel.name.to_string()), channel.sample_type
+        ))
+        .collect::<Vec<_>>();
+
+    let chromaticities = match header.shared_attributes.chromaticities {
+        Some(c) => format!(
+            "[{},{},{},{},{},{},{},{}]",
+            c.red.x(), c.red.y(), c.green.x(), c.green.y(), c.blue.x(), c.blue.y(), c.white.x(), c.white.y()
+        ),
+        None => "null".to_string(),
+    };
+
+    let blocks = match header.blocks {
+        BlockDescription::ScanLines => "ScanLines",
+        BlockDescription::Tiles(_) => "Tiles",
+    };
+
+    let levels = match exr_level_mode(header.blocks) {
+        LEVEL_MODE_MIP_MAP => "MipMap",
+        LEVEL_MODE_RIP_MAP => "RipMap",
+        _ => "Singular",
+    };
+
+    format!(
+        "{{\"name\":{},\"width\":{},\"height\":{},\"encoding\":\"{:?}\",\"blocks\":\"{}\",\"levels\":\"{}\",\"deep\":{},\"channels\":[{}],\"chromaticities\":{}}}",
+        name, header.layer_size.width(), header.layer_size.height(), ExrEncoding::from(header.compression),
+        blocks, levels, header.deep, channels.join(","), chromaticities
+    )
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Lists the custom string attributes of the layer called `layer_name`, null or empty meaning the first layer,
+/// including those shared by every layer of the file. The `out_count` names and values are stored in `out_names`
+/// and `out_values`, sorted by name, and both arrays must be released with `free_string_array`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string and the out pointers must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn read_string_attributes(
+    path: *const c_char, layer_name: *const c_char, out_count: *mut u32,
+    out_names: *mut *mut *mut c_char, out_values: *mut *mut *mut c_char
+) -> ExrStatus {
+    *out_count = 0;
+    *out_names = std::ptr::null_mut();
+    *out_values = std::ptr::null_mut();
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let (meta, layer_index) = match find_layer(ReadSource::File(path_str), read_optional_str(layer_name), false) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+
+    let header = &meta.headers[layer_index];
+    let mut attributes = header.own_attributes.other.iter()
+        .chain(&header.shared_attributes.other)
+        .filter_map(|(name, value)| match value {
+            AttributeValue::Text(text) => Some((name.to_string(), text.to_string())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    attributes.sort();
+
+    *out_count = attributes.len() as u32;
+    let (names, values): (Vec<_>, Vec<_>) = attributes.into_iter()
+        .map(|(name, value)| (into_c_string(name), into_c_string(value)))
+        .unzip();
+    *out_names = Box::into_raw(names.into_boxed_slice()) as *mut *mut c_char;
+    *out_values = Box::into_raw(values.into_boxed_slice()) as *mut *mut c_char;
+
+    ExrStatus::Ok
+}
+
+/// Describes the attributes of every layer of `path` as JSON in `out_json`, keyed by their names in the file, e.g.
+/// `{"layers":[{"name":"beauty","attributes":{"owner":"studio","pixelAspectRatio":1,"shot":"sh010"}}]}`.
+/// Besides the standard text and number attributes, custom attributes of type string, string vector, int, float,
+/// double, rational and 2D or 3D vector are included, attributes shared by all layers being repeated on each.
+/// The string must be released with `free_string`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string and `out_json` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn read_attributes(path: *const c_char, out_json: *mut *mut c_char) -> ExrStatus {
+    *out_json = std::ptr::null_mut();
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let meta = unwrap_or_return_err!(MetaData::read_from_file(path_str, false));
+
+    let layers = meta.headers.iter()
+        .map(|header| {
+            let name = header.own_attributes.layer_name.as_ref().map_or("null".to_string(), |name| json_string(&name.to_string()));
+            format!("{{\"name\":{},\"attributes\":{}}}", name, attributes_json(header))
+        })
+        .collect::<Vec<_>>();
+
+    *out_json = into_c_string(format!("{{\"layers\":[{}]}}", layers.join(",")));
+    ExrStatus::Ok
+}
+
+fn attributes_json(header: &Header) -> String {
+    let (image, layer) = (&header.shared_attributes, &header.own_attributes);
+    let text = |value: &Option<Text>| value.clone().map(AttributeValue::Text);
+    let float = |value: Option<f32>| value.map(AttributeValue::F32);
+
+    let standard = [
+        ("pixelAspectRatio", Some(AttributeValue::F32(image.pixel_aspect))),
+        ("owner", text(&layer.owner)),
+        ("comments", text(&layer.comments)),
+        ("capDate", text(&layer.capture_date)),
+        ("software", text(&layer.software_name)),
+        ("view", text(&layer.view_name)),
+        ("wrapmodes", text(&layer.wrap_mode_name)),
+        ("renderingTransform", text(&layer.rendering_transform_name)),
+        ("lookModTransform", text(&layer.look_modification_transform_name)),
+        ("whiteLuminance", float(layer.white_luminance)),
+        ("xDensity", float(layer.horizontal_density)),
+        ("utcOffset", float(layer.utc_offset)),
+        ("longitude", float(layer.longitude)),
+        ("latitude", float(layer.latitude)),
+        ("altitude", float(layer.altitude)),
+        ("focus", float(layer.focus)),
+        ("expTime", float(layer.exposure)),
+        ("aperture", float(layer.aperture)),
+        ("isoSpeed", float(layer.iso_speed)),
+        ("near", float(layer.near_clip_plane)),
+        ("far", float(layer.far_clip_plane)),
+        ("fieldOfViewHorizontal", float(layer.horizontal_field_of_view)),
+        ("fieldOfViewVertical", float(layer.vertical_field_of_view)),
+        ("framesPerSecond", layer.frames_per_second.map(AttributeValue::Rational)),
+    ];
+
+    let mut attributes = standard.into_iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value?)))
+        .chain(image.other.iter().chain(&layer.other).map(|(name, value)| (name.to_string(), value.clone())))
+        .filter_map(|(name, value)| Some(format!("{}:{}", json_string(&name), attribute_value_json(&value)?)))
+        .collect::<Vec<_>>();
+    attributes.sort();
+
+    format!("{{{}}}", attributes.join(","))
+}
+
+// Attribute types without a natural JSON representation are left out
+fn attribute_value_json(value: &AttributeValue) -> Option<String> {
+    let json = match value {
+        AttributeValue::Text(text) => json_string(&text.to_string()),
+        AttributeValue::TextVector(texts) => format!(
+            "[{}]", texts.iter().map(|text| json_string(&text.to_string())).collect::<Vec<_>>().join(",")
+        ),
+        AttributeValue::I32(value) => value.to_string(),
+        AttributeValue::F32(value) => json_number(*value),
+        AttributeValue::F64(value) => json_number(*value),
+        AttributeValue::Rational((numerator, denominator)) => format!("[{},{}]", numerator, denominator),
+        AttributeValue::IntVec2(vec) => format!("[{},{}]", vec.x(), vec.y()),
+        AttributeValue::FloatVec2(vec) => format!("[{},{}]", json_number(vec.x()), json_number(vec.y())),
+        AttributeValue::IntVec3((x, y, z)) => format!("[{},{},{}]", x, y, z),
+        AttributeValue::FloatVec3((x, y, z)) => format!(
+            "[{},{},{}]", json_number(*x), json_number(*y), json_number(*z)
+        ),
+        _ => return None,
+    };
+
+    Some(json)
+}
+
+// JSON has no representation for infinities and NaN
+fn json_number<T: Copy + Display + Into<f64>>(value: T) -> String {
+    if value.into().is_finite() { value.to_string() } else { "null".to_string() }
+}
+
+/// Releases an array of `count` strings returned by this library, such as the names of `read_string_attributes`.
+///
+/// # Safety
+/// `strings` must be null or come from this library together with its `count`.
+#[no_mangle]
+pub unsafe extern "C" fn free_string_array(strings: *mut *mut c_char, count: u32) {
+    if !strings.is_null() {
+        for string in Vec::from_raw_parts(strings, count as usize, count as usize) {
+            free_string(string);
+        }
+    }
+}
+
+/// Releases a string returned by this library, such as the JSON of `read_info`.
+///
+/// # Safety
+/// `string` must be null or a pointer handed out by this library that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+fn into_c_string(string: String) -> *mut c_char {
+    // Interior nul bytes cannot cross the boundary, so they are dropped
+    CString::new(string.replace('\0', "")).unwrap().into_raw()
+}
+
+// Hands the pixels over to the caller. Going through a boxed slice drops any spare capacity,
+// so `free_texture` can rebuild the allocation from the pixel count alone.
+fn into_raw<T>(pixels: Vec<T>) -> *mut c_void {
+    Box::into_raw(pixels.into_boxed_slice()) as *mut c_void
+}
+
+/// Decodes like `load_from_path`, but into the caller's `dst` buffer of `dst_len_bytes` instead of
+/// allocating one. The out parameters are always filled once the file could be inspected, so a caller can
+/// size its buffer from them, or beforehand with `required_buffer_size`. Returns `ExrStatus::BufferTooSmall` when `dst` cannot hold the pixels.
+/// `row_stride_bytes` is the distance from the start of one row in `dst` to the next, such as the row pitch of
+/// aligned staging memory, 0 meaning tightly packed rows. It must be a multiple of the sample size and hold a whole
+/// row, or the load fails with `ExrStatus::InvalidArgument`. The padding between the rows is left untouched.
+/// `pedantic` validates the file like it does for `load_from_path`. Of the other `ExrLoadOptions` there is no
+/// `force_exr`, as the format is always told by `file_kind`, and no `memory_map`, the file being read in buffered
+/// chunks. Luminance and chroma layers are rejected with `ExrStatus::Unsupported`, since their RGB is only computed
+/// into a buffer of its own, with the out parameters left at -1 like for files that cannot be inspected.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string, the out pointers must be
+/// writable and `dst` must be valid for `dst_len_bytes` bytes, aligned for the element type of the layer.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn load_into(
+    path: *const c_char, layer_name: *const c_char, pedantic: bool, dst: *mut c_void, dst_len_bytes: usize,
+    row_stride_bytes: usize, width: *mut i32, height: *mut i32, num_channels: *mut i32, format: *mut ExrPixelFormat
+) -> ExrStatus {
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let kind = file_kind(path_str);
+
+    if kind == FileKind::Radiance {
+        let image = unwrap_or_return_err!(radiant::load(unwrap_or_return_err!(open_radiance(path_str))));
+
+        *width = image.width as i32;
+        *height = image.height as i32;
+        *num_channels = 3;
+        *format = ExrPixelFormat::RGBF32;
+
+        let samples = from_raw_parts(image.data.as_ptr() as *const f32, image.data.len() * 3);
+        return copy_rows_into(samples, image.width * 3, row_stride_bytes, dst, dst_len_bytes);
+    }
+
+    if kind == FileKind::Pfm {
+        let file = unwrap_or_return_err!(File::open(path_str));
+        let image = unwrap_or_return_err!(load_pfm(BufReader::new(file)));
+
+        *width = image.width as i32;
+        *height = image.height as i32;
+        *num_channels = image.num_channels as i32;
+        *format = image.format();
+
+        return copy_rows_into(&image.data, image.width * image.num_channels, row_stride_bytes, dst, dst_len_bytes);
+    }
+
+    let (meta, layer_index) = match find_layer(ReadSource::File(path_str), read_optional_str(layer_name), pedantic) {
+        Ok(found) => found,
+        Err(status) => return status,
+    };
+
+    let header = &meta.headers[layer_index];
+    if is_subsampled(header) {
+        set_last_error("luminance and chroma layers cannot be loaded into a buffer, use load_from_path");
+        return ExrStatus::Unsupported;
+    }
+
+    let order = channel_order(&header.channels);
+    let exr_format = header.channels.uniform_sample_type.map_or(ExrPixelFormat::F32, ExrPixelFormat::from);
+
+    *width = header.layer_size.width() as i32;
+    *height = header.layer_size.height() as i32;
+    *num_channels = order.len() as i32;
+    *format = exr_format;
+
+    let row_len = header.layer_size.width() * order.len();
+    let Some(row_stride) = row_stride_samples(row_len, exr_format.element_size(), row_stride_bytes) else {
+        set_last_error(format!("invalid row stride of {} bytes for {} {:?} samples", row_stride_bytes, row_len, exr_format));
+        return ExrStatus::InvalidArgument;
+    };
+
+    let len = strided_len(row_len, row_stride, header.layer_size.height());
+    if dst_len_bytes < len * exr_format.element_size() {
+        set_last_error(format!("the pixels need {} bytes, the buffer holds {}", len * exr_format.element_size(), dst_len_bytes));
+        return ExrStatus::BufferTooSmall;
+    }
+
+    let source = ReadSource::File(path_str);
+    let hooks = ReadHooks { row_stride, pedantic, ..Default::default() };
+    let result = match header.channels.uniform_sample_type {
+        Some(SampleType::F16) => source.decode_into(layer_index, Vec2(0, 0), &order, f16::ONE, hooks, from_raw_parts_mut(dst as *mut f16, len)),
+        Some(SampleType::F32) | None => source.decode_into(layer_index, Vec2(0, 0), &order, 1.0, hooks, from_raw_parts_mut(dst as *mut f32, len)),
+        Some(SampleType::U32) => source.decode_into(layer_index, Vec2(0, 0), &order, 1, hooks, from_raw_parts_mut(dst as *mut u32, len)),
+    };
+
+    unwrap_or_return_err!(result);
+    ExrStatus::Ok
+}
+
+/// Reads only the headers of `path` and stores the exact number of bytes `load_into` needs for `dst` in `out_bytes`,
+/// together with the `width`, `height`, `num_channels` and `format` it would return, so hosts can allocate before
+/// loading. `layer_name` and `row_stride_bytes` are the ones that will be passed to `load_into`. With padded rows,
+/// the last row only needs room for its pixels. The out parameters are 0, -1 and Unknown unless this returns
+/// `ExrStatus::Ok`.
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, `layer_name` null or a valid string, and the out pointers must be
+/// writable.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn required_buffer_size(
+    path: *const c_char, layer_name: *const c_char, row_stride_bytes: usize, out_bytes: *mut usize, width: *mut i32,
+    height: *mut i32, num_channels: *mut i32, format: *mut ExrPixelFormat
+) -> ExrStatus {
+    *out_bytes = 0;
+    *width = -1;
+    *height = -1;
+    *num_channels = -1;
+    *format = ExrPixelFormat::Unknown;
+
+    let path_str = unwrap_or_return_err!(CStr::from_ptr(path).to_str());
+    let (size, channels, sample_format) = match file_kind(path_str) {
+        FileKind::Radiance => {
+            let loader = unwrap_or_return_err!(radiant::Loader::new(unwrap_or_return_err!(open_radiance(path_str))));
+            (Vec2(loader.width, loader.height), 3, ExrPixelFormat::RGBF32)
+        },
+        FileKind::Pfm => {
+            let mut read = BufReader::new(unwrap_or_return_err!(File::open(path_str)));
+            let (pfm_width, pfm_height, pfm_channels, _) = unwrap_or_return_err!(read_pfm_header(&mut read));
+            let pfm_format = if pfm_channels == 3 { ExrPixelFormat::RGBF32 } else { ExrPixelFormat::F32 };
+            (Vec2(pfm_width, pfm_height), pfm_channels, pfm_format)
+        },
+        FileKind::Exr => {
+            let (meta, layer_index) = match find_layer(ReadSource::File(path_str), read_optional_str(layer_name), false) {
+                Ok(found) => found,
+                Err(status) => return status,
+            };
+
+            let header = &meta.headers[layer_index];
+            let exr_format = header.channels.uniform_sample_type.map_or(ExrPixelFormat::F32, ExrPixelFormat::from);
+            (header.layer_size, channel_order(&header.channels).len(), exr_format)
+        },
+    };
+
+    let row_len = size.width() * channels;
+    let Some(row_stride) = row_stride_samples(row_len, sample_format.element_size(), row_stride_bytes) else {
+        set_last_error(format!("invalid row stride of {} bytes for {} {:?} samples", row_stride_bytes, row_len, sample_format));
+        return ExrStatus::InvalidArgument;
+    };
+
+    *out_bytes = strided_len(row_len, row_stride, size.height()) * sample_format.element_size();
+    *width = size.width() as i32;
+    *height = size.height() as i32;
+    *num_channels = channels as i32;
+    *format = sample_format;
+    ExrStatus::Ok
+}
+
+// Copies rows of `row_len` samples to `dst`, starting `row_stride_bytes` apart
+unsafe fn copy_rows_into(samples: &[f32], row_len: usize, row_stride_bytes: usize, dst: *mut c_void, dst_len_bytes: usize) -> ExrStatus {
+    let Some(row_stride) = row_stride_samples(row_len, mem::size_of::<f32>(), row_stride_bytes) else {
+        set_last_error(format!("invalid row stride of {} bytes for {} F32 samples", row_stride_bytes, row_len));
+        return ExrStatus::InvalidArgument;
+    };
+
+    let len = strided_len(row_len, row_stride, samples.len() / row_len.max(1));
+    if dst_len_bytes < len * mem::size_of::<f32>() {
+        set_last_error(format!("the pixels need {} bytes, the buffer holds {}", len * mem::size_of::<f32>(), dst_len_bytes));
+        return ExrStatus::BufferTooSmall;
+    }
+
+    let dst = from_raw_parts_mut(dst as *mut f32, len);
+    for (row, dst_row) in samples.chunks(row_len.max(1)).zip(rows_mut(dst, row_len, row_stride)) {
+        dst_row.copy_from_slice(row);
+    }
+
+    ExrStatus::Ok
+}
+
+// Reads the headers of `source` and looks up the layer called `layer_name`, defaulting to the first one, rejecting
+// any deviation from the specification if `pedantic`. Failures are already stored for `get_last_error`.
+fn find_layer(source: ReadSource, layer_name: Option<String>, pedantic: bool) -> std::result::Result<(MetaData, usize), ExrStatus> {
+    let meta = match source.meta_data(pedantic) {
+        Ok(meta) => meta,
+        Err(e) => {
+            set_last_error(&e);
+            return Err(ExrStatus::from(&e));
+        }
+    };
+
+    let index = layer_index(&meta.headers, layer_name)?;
+    Ok((meta, index))
+}
+
+// Looks up the layer called `layer_name` like `header_index`, storing the failure for `get_last_error`
+fn layer_index(headers: &[Header], layer_name: Option<String>) -> std::result::Result<usize, ExrStatus> {
+    header_index(headers, layer_name.as_deref()).ok_or_else(|| {
+        set_last_error(format!("no layer named \"{}\"", layer_name.unwrap_or_default()));
+        ExrStatus::InvalidArgument
+    })
+}
+
+// Colour layers are handed out as RGBA regardless of the alphabetical order exr stores channels in,
+// `None` marking a missing alpha channel that is filled with one. Channels may carry a layer prefix
+// such as "beauty.R" as long as the match is unambiguous. Any other layer keeps the channel order of the file.
+fn channel_order(channels: &ChannelList) -> Vec<Option<usize>> {
+    let index_of = |name: &str| {
+        channels.find_index_of_channel(&Text::from(name)).or_else(|| {
+            let suffix = format!(".{}", name);
+            let mut matches = channels.list.iter().enumerate()
+                .filter(|(_, channel)| channel.name.to_string().ends_with(&suffix))
+                .map(|(index, _)| index);
+
+            match (matches.next(), matches.next()) {
+                (Some(index), None) => Some(index),
+                _ => None,
+            }
+        })
+    };
+
+    match (index_of("R"), index_of("G"), index_of("B")) {
+        (Some(r), Some(g), Some(b)) => vec![Some(r), Some(g), Some(b), index_of("A")],
+        _ => (0..channels.list.len()).map(Some).collect(),
+    }
+}
+
+// Keeps the channels of `order` that `channel_mask` selects
+fn select_channels(order: Vec<Option<usize>>, channel_mask: u32) -> Vec<Option<usize>> {
+    order.into_iter().enumerate()
+        .filter(|(slot, _)| is_selected(channel_mask, *slot))
+        .map(|(_, channel)| channel)
+        .collect()
+}
+
+// Bit `slot` selects the channel at that position of a pixel, a mask of 0 selecting all of them
+fn is_selected(channel_mask: u32, slot: usize) -> bool {
+    channel_mask == 0 || (slot < 32 && channel_mask >> slot & 1 == 1)
+}
+
+// Drops the channels `channel_mask` leaves out of a decoded layer, releasing its pixels if none remain
+unsafe fn retain_channels(layer: LoadedLayer, size: Vec2<usize>, channel_mask: u32) -> Option<LoadedLayer> {
+    if channel_mask == 0 {
+        return Some(layer);
+    }
+
+    let kept = (0..layer.num_channels).filter(|&slot| is_selected(channel_mask, slot)).collect::<Vec<_>>();
+    let pixels = match kept.is_empty() {
+        true => None,
+        false => Some(match layer.format {
+            ExrPixelFormat::U32 => into_raw(retain_samples::<u32>(layer.pixels, size.area(), layer.num_channels, &kept)),
+            ExrPixelFormat::F16 => into_raw(retain_samples::<f16>(layer.pixels, size.area(), layer.num_channels, &kept)),
+            _ => into_raw(retain_samples::<f32>(layer.pixels, size.area(), layer.num_channels, &kept)),
+        }),
+    };
+
+    free_texture(layer.pixels, size.width() as u32, size.height() as u32, layer.num_channels as u32, layer.format);
+
+    let names = layer.channel_names.split('\n').collect::<Vec<_>>();
+    Some(LoadedLayer {
+        pixels: pixels?,
+        format: layer.format,
+        num_channels: kept.len(),
+        channel_names: kept.iter().map(|&slot| names[slot]).collect::<Vec<_>>().join("\n"),
+        channel_formats: kept.iter().map(|&slot| layer.channel_formats[slot]).collect(),
+        rgba: layer.rgba && kept.len() == layer.num_channels,
+        num_layers: layer.num_layers,
+    })
+}
+
+unsafe fn retain_samples<T: Copy>(pixels: *const c_void, num_pixels: usize, num_channels: usize, kept: &[usize]) -> Vec<T> {
+    from_raw_parts(pixels as *const T, num_pixels * num_channels)
+        .chunks_exact(num_channels)
+        .flat_map(|pixel| kept.iter().map(|&slot| pixel[slot]))
+        .collect()
+}
+
+// Whether `order` picks stored R, G, B and A channels, in that order
+fn is_rgba(channels: &ChannelList, order: &[Option<usize>]) -> bool {
+    order.len() == 4 && order.iter().zip(["R", "G", "B", "A"]).all(|(index, expected)| index.is_some_and(|index| {
+        let name = channels.list[index].name.to_string();
+        name == expected || name.ends_with(&format!(".{}", expected))
+    }))
+}
+
+// Divides the color channels of RGBA pixels by alpha, leaving fully transparent ones alone
+fn unpremultiply_alpha<T: IntoNativeSample + FromNativeSample>(pixels: &mut [T]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3].to_f32();
+        if alpha != 0.0 {
+            for color in &mut pixel[..3] {
+                *color = T::from_f32(color.to_f32() / alpha);
+            }
+        }
+    }
+}
+
+// Converts pixels allocated by `into_raw` to `requested`, releasing the originals. RGBF32 pixels already are F32.
+unsafe fn convert_texture(
+    pixels: *mut c_void, width: usize, height: usize, num_channels: usize, format: ExrPixelFormat, requested: ExrPixelFormat
+) -> (*mut c_void, ExrPixelFormat) {
+    let native = if format == ExrPixelFormat::RGBF32 { ExrPixelFormat::F32 } else { format };
+    if requested == ExrPixelFormat::Unknown || requested == native {
+        return (pixels, format);
+    }
+
+    let len = width * height * num_channels;
+    let converted = match requested {
+        ExrPixelFormat::F32 if native == ExrPixelFormat::F16 => into_raw(widen_f16(from_raw_parts(pixels as *const f16, len))),
+        ExrPixelFormat::U32 => into_raw(convert_samples::<u32>(pixels, len, native)),
+        ExrPixelFormat::F16 => into_raw(convert_samples::<f16>(pixels, len, native)),
+        _ => into_raw(convert_samples::<f32>(pixels, len, native)),
+    };
+
+    free_texture(pixels, width as u32, height as u32, num_channels as u32, format);
+    (converted, requested)
+}
+
+// Converts many samples at once with the F16C or NEON instructions where the CPU has them, which gives the same bits
+// as `f16::to_f32` on each sample, NaNs included
+fn widen_f16(samples: &[f16]) -> Vec<f32> {
+    let mut widened = vec![0.0; samples.len()];
+    samples.convert_to_f32_slice(&mut widened);
+    widened
+}
+
+unsafe fn convert_samples<T: FromNativeSample>(pixels: *const c_void, len: usize, format: ExrPixelFormat) -> Vec<T> {
+    match format {
+        ExrPixelFormat::U32 => from_raw_parts(pixels as *const u32, len).iter().map(|&sample| T::from_u32(sample)).collect(),
+        ExrPixelFormat::F16 => from_raw_parts(pixels as *const f16, len).iter().map(|&sample| T::from_f16(sample)).collect(),
+        _ => from_raw_parts(pixels as *const f32, len).iter().map(|&sample| T::from_f32(sample)).collect(),
+    }
+}
+
+// A filled in alpha channel is reported as "A"
+fn ordered_channel_names(channels: &ChannelList, order: &[Option<usize>]) -> String {
+    order.iter()
+        .map(|index| index.map_or("A".to_string(), |index| channels.list[index].name.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn ordered_channel_formats(channels: &ChannelList, order: &[Option<usize>]) -> Vec<ExrPixelFormat> {
+    order.iter()
+        .map(|index| index.map_or(ExrPixelFormat::Unknown, |index| channels.list[index].sample_type.into()))
+        .collect()
+}
+
+unsafe fn set_num_layers(num_layers: *mut i32, value: usize) {
+    if !num_layers.is_null() {
+        *num_layers = value as i32;
+    }
+}
+
+// Measures the pixels on the rayon pool, summing in f64 so that the mean of large frames keeps its precision
+unsafe fn measure_luminance(pixels: *const c_void, num_pixels: usize, format: ExrPixelFormat, channel_names: &str) -> ExrLuminanceStats {
+    let names = channel_names.split('\n').collect::<Vec<_>>();
+    let find = |wanted: &str| names.iter().position(|name| name.rsplit('.').next() == Some(wanted));
+    let weights = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(red), Some(green), Some(blue), _) => vec![red, green, blue].into_iter().zip(LUMINANCE_WEIGHTS.map(f64::from)).collect(),
+        (.., Some(luminance)) => vec![(luminance, 1.0)],
+        _ => vec![(0, 1.0)],
+    };
+
+    fn measure<T: Copy + Sync>(samples: &[T], num_channels: usize, weights: &[(usize, f64)], to_f64: fn(T) -> f64) -> (f64, f64, f64, usize) {
+        samples.par_chunks(num_channels * 4096)
+            .map(|pixels| pixels.chunks_exact(num_channels).fold((f64::INFINITY, f64::NEG_INFINITY, 0.0, 0), |(min, max, sum, count), pixel| {
+                let luminance = weights.iter().map(|&(channel, weight)| to_f64(pixel[channel]) * weight).sum::<f64>();
+                if luminance.is_finite() { (min.min(luminance), max.max(luminance), sum + luminance, count + 1) } else { (min, max, sum, count) }
+            }))
+            .reduce(|| (f64::INFINITY, f64::NEG_INFINITY, 0.0, 0), |a, b| (a.0.min(b.0), a.1.max(b.1), a.2 + b.2, a.3 + b.3))
+    }
+
+    let len = num_pixels * names.len();
+    let (min, max, sum, count) = match format {
+        ExrPixelFormat::U32 => measure(from_raw_parts(pixels as *const u32, len), names.len(), &weights, |sample| sample as f64),
+        ExrPixelFormat::F16 => measure(from_raw_parts(pixels as *const f16, len), names.len(), &weights, f16::to_f64),
+        ExrPixelFormat::F32 | ExrPixelFormat::RGBF32 => measure(from_raw_parts(pixels as *const f32, len), names.len(), &weights, f64::from),
+        ExrPixelFormat::Unknown => return ExrLuminanceStats::default(),
+    };
+
+    if count == 0 {
+        return ExrLuminanceStats::default();
+    }
+
+    ExrLuminanceStats { min: min as f32, max: max as f32, mean: (sum / count as f64) as f32 }
+}
+
+// Entries past the returned channels are left unknown
+unsafe fn set_channel_formats(channel_formats: *mut ExrPixelFormat, len: u32, formats: &[ExrPixelFormat]) {
+    if !channel_formats.is_null() {
+        for (index, slot) in from_raw_parts_mut(channel_formats, len as usize).iter_mut().enumerate() {
+            *slot = formats.get(index).copied().unwrap_or(ExrPixelFormat::Unknown);
+        }
+    }
+}
+
+// A filled in alpha channel, which the file does not store, does not count
+fn is_stored_alpha(channel_names: &str, channel_formats: &[ExrPixelFormat]) -> bool {
+    channel_names.split('\n').zip(channel_formats)
+        .any(|(name, &format)| format != ExrPixelFormat::Unknown && is_alpha(name))
+}
+
+unsafe fn set_load_info(info: *mut ExrLoadInfo, value: ExrLoadInfo) {
+    if !info.is_null() {
+        *info = value;
+    }
+}
+
+unsafe fn set_channel_names(channel_names: *mut *mut c_char, names: Option<String>) {
+    if !channel_names.is_null() {
+        *channel_names = names.map_or(std::ptr::null_mut(), into_c_string);
+    }
+}
+
+// Changing a mapped file while it is read is undefined behaviour, which `load_from_path` leaves to its caller to rule out
+fn map_file(path: &str) -> Option<memmap2::Mmap> {
+    let file = File::open(path).ok()?;
+    unsafe { memmap2::Mmap::map(&file) }.ok()
+}
+
+#[derive(Clone, Copy)]
+enum ReadSource<'a> {
+    File(&'a str),
+    Memory(&'a [u8]),
+}
+
+impl ReadSource<'_> {
+    fn meta_data(self, pedantic: bool) -> Result<MetaData> {
+        match self {
+            ReadSource::File(path) => MetaData::read_from_file(path, pedantic),
+            ReadSource::Memory(bytes) => MetaData::read_from_buffered(Cursor::new(bytes), pedantic),
+        }
+    }
+
+    // Reads the headers like `meta_data`, along with the position of the offset tables following them
+    fn meta_data_and_tables(self, pedantic: bool) -> Result<(MetaData, u64)> {
+        fn read_headers(read: impl Read, pedantic: bool) -> Result<(MetaData, u64)> {
+            let mut read = PeekRead::new(Tracking::new(read));
+            exr::meta::magic_number::validate_exr(&mut read)?;
+            let requirements = Requirements::read(&mut read)?;
+            requirements.validate()?;
+            let headers = Header::read_all(&mut read, &requirements, pedantic)?;
+            Ok((MetaData { requirements, headers }, read.byte_position() as u64))
+        }
+
+        match self {
+            ReadSource::File(path) => read_headers(BufReader::new(File::open(path)?), pedantic),
+            ReadSource::Memory(bytes) => read_headers(bytes, pedantic),
+        }
+    }
+
+    // Decodes the subsampled layer at `layer_index`, see `load_subsampled_layer`
+    fn load_subsampled(self, meta: &MetaData, layer_index: usize, tables_start: u64) -> Result<LoadedLayer> {
+        match self {
+            ReadSource::File(path) => load_subsampled_layer(BufReader::new(File::open(path)?), meta, layer_index, tables_start),
+            ReadSource::Memory(bytes) => load_subsampled_layer(Cursor::new(bytes), meta, layer_index, tables_start),
+        }
+    }
+
+    // Decodes resolution `level` of the layer at `layer_index` into `flat_data`, interleaved in `order`
+    fn decode_into<T: FromNativeSample + FiniteSample + Send + Sync>(
+        self, layer_index: usize, level: Vec2<usize>, order: &[Option<usize>], one: T, hooks: ReadHooks, flat_data: &mut [T]
+    ) -> Result<()> {
+        match self {
+            ReadSource::File(path) => decode_layer_into(BufReader::new(File::open(path)?), layer_index, level, order, one, hooks, flat_data),
+            ReadSource::Memory(bytes) => decode_layer_into(Cursor::new(bytes), layer_index, level, order, one, hooks, flat_data),
+        }
+    }
+}
+
+// Reads the headers and the chunks of the blocks `filter` keeps, in the order they are stored. This stands in for
+// `exr::block::read`, which parses the headers strictly exactly when asked to be lenient and the other way around,
+// so that malformed attributes are only rejected if `pedantic`.
+// With `recover`, an offset table that was never filled in, as left behind by a writer that crashed, is rebuilt
+// from the chunks that made it into the file, which are read one after the other up to the first broken one.
+fn read_chunks<'a>(
+    read: impl Read + Seek + Send + 'a, pedantic: bool, recover: bool, filter: impl Fn(BlockIndex) -> bool
+) -> Result<(MetaData, ChunkIter<'a>)> {
+    let mut read = PeekRead::new(Tracking::new(read));
+    exr::meta::magic_number::validate_exr(&mut read)?;
+    let requirements = Requirements::read(&mut read)?;
+    requirements.validate()?;
+    let headers = Header::read_all(&mut read, &requirements, pedantic)?;
+    MetaData::validate(&headers, pedantic)?;
+
+    let offset_tables = MetaData::read_offset_tables(&mut read, &headers)?;
+    let chunks_start = read.byte_position() as u64;
+    let meta = MetaData { requirements, headers };
+
+    if recover && offset_tables.iter().flatten().any(|&offset| offset < chunks_start) {
+        let mut chunks = Vec::new();
+        while let Ok(chunk) = Chunk::read(&mut read, &meta) {
+            if block_index(&meta.headers, &chunk).is_ok_and(&filter) {
+                chunks.push(Ok(chunk));
+            }
+        }
+
+        return Ok((meta, Box::new(chunks.into_iter())));
+    }
+
+    let mut offsets = Vec::new();
+    for (layer, (header, offset_table)) in meta.headers.iter().zip(&offset_tables).enumerate() {
+        for (tile, &offset) in header.blocks_increasing_y_order().zip(offset_table) {
+            let bounds = header.get_absolute_block_pixel_coordinates(tile.location)?;
+            let block = BlockIndex {
+                layer,
+                level: tile.location.level_index,
+                pixel_position: bounds.position.to_usize("block position")?,
+                pixel_size: bounds.size,
+            };
+
+            if filter(block) {
+                offsets.push(offset);
+            }
+        }
+    }
+
+    // Sorted to read the file front to back, which also puts any offset listed twice next to each other
+    offsets.sort_unstable();
+    if pedantic && offsets.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(Error::Invalid("chunk offset table".into()));
+    }
+
+    let chunk_meta = meta.clone();
+    let chunks = offsets.into_iter().map(move |offset| {
+        read.skip_to(usize::try_from(offset).map_err(|_| Error::Invalid("chunk offset".into()))?)?;
+        Chunk::read(&mut read, &chunk_meta)
+    });
+
+    Ok((meta, Box::new(chunks)))
+}
+
+type ChunkIter<'a> = Box<dyn ExactSizeIterator<Item = Result<Chunk>> + Send + 'a>;
+
+// Where the block a chunk holds lies, as `filter_chunks` would describe it
+fn block_index(headers: &[Header], chunk: &Chunk) -> Result<BlockIndex> {
+    let header = headers.get(chunk.layer_index).ok_or_else(|| Error::Invalid("chunk layer".into()))?;
+    let tile = header.get_block_data_indices(&chunk.compressed_block)?;
+    let bounds = header.get_absolute_block_pixel_coordinates(tile)?;
+
+    Ok(BlockIndex {
+        layer: chunk.layer_index,
+        level: tile.level_index,
+        pixel_position: bounds.position.to_usize("block position")?,
+        pixel_size: bounds.size,
+    })
+}
+
+// Every decompressed line is written straight to its place in `flat_data` instead of collecting whole channels
+// first, so a frame is never held twice in memory. Mixed sample types are converted to `T` on the way.
+// Blocks are decompressed and interleaved on the rayon pool, each thread filling the pixels of its own blocks.
+fn decode_layer_into<T: FromNativeSample + FiniteSample + Send + Sync>(
+    read: impl Read + Seek + Send, layer_index: usize, level: Vec2<usize>, order: &[Option<usize>], one: T, hooks: ReadHooks,
+    flat_data: &mut [T]
+) -> Result<()> {
+    // Blocks outside of the region are never read, let alone decompressed
+    let overlaps = |block: BlockIndex| hooks.region.is_none_or(|(origin, extent)| {
+        block.pixel_position.x() < origin.x() + extent.width() && origin.x() < block.pixel_position.x() + block.pixel_size.width()
+            && block.pixel_position.y() < origin.y() + extent.height() && origin.y() < block.pixel_position.y() + block.pixel_size.height()
+    });
+    let recover = hooks.missing_rows.is_some();
+    let (meta, chunks) = read_chunks(read, hooks.pedantic, recover, |block| {
+        block.layer == layer_index && block.level == level && overlaps(block)
+    })?;
+    let header = meta.headers.get(layer_index).ok_or_else(|| Error::Invalid("layer index".into()))?;
+    let size = level_size(header, level).ok_or_else(|| Error::Invalid("resolution level".into()))?;
+    let (block_size, num_channels) = (header.max_block_pixel_size(), order.len());
+    let (origin, extent) = hooks.region.unwrap_or((Vec2(0, 0), size));
+
+    if origin.x() + extent.width() > size.width() || origin.y() + extent.height() > size.height() {
+        return Err(Error::Invalid("region".into()));
+    }
+
+    let row_len = extent.width() * num_channels;
+    let row_stride = if hooks.row_stride == 0 { row_len } else { hooks.row_stride };
+    if row_stride < row_len || flat_data.len() != strided_len(row_len, row_stride, extent.height()) {
+        return Err(Error::Invalid("buffer size".into()));
+    }
+
+    if block_size.area() == 0 {
+        return Err(Error::Invalid("block size".into()));
+    }
+
+    // Where each channel of the file ends up within a pixel, if at all
+    let mut slots = vec![None; header.channels.list.len()];
+    for (slot, channel) in order.iter().enumerate() {
+        match channel {
+            Some(channel) => slots[*channel] = Some(slot),
+            None => rows_mut(flat_data, row_len, row_stride)
+                .flat_map(|row| row.iter_mut().skip(slot).step_by(num_channels))
+                .for_each(|sample| *sample = one),
+        }
+    }
+
+    let (blocks_x, blocks_y) = (size.width().div_ceil(block_size.width()), size.height().div_ceil(block_size.height()));
+    let claimed = (0..blocks_x * blocks_y).map(|_| AtomicBool::new(false)).collect::<Vec<_>>();
+    let decoded = (0..blocks_x * blocks_y).map(|_| AtomicBool::new(false)).collect::<Vec<_>>();
+    let output = SharedOutput(flat_data.as_mut_ptr());
+
+    let total_chunks = chunks.len();
+    let decoded_chunks = Mutex::new(0);
+    hooks.report_progress(0.0);
+
+    let decode_chunk = |chunk: Result<Chunk>| {
+        let block = UncompressedBlock::decompress_chunk(chunk?, &meta, hooks.pedantic)?;
+        let (position, block_pixels) = (block.index.pixel_position, block.index.pixel_size);
+
+        // Only a grid aligned block inside the image that was not seen before may be written, which keeps the
+        // pixels of the threads disjoint even for a broken file
+        let aligned = position.x() % block_size.width() == 0 && position.y() % block_size.height() == 0;
+        let inside = block_pixels.width() <= block_size.width() && block_pixels.height() <= block_size.height()
+            && position.x() + block_pixels.width() <= size.width() && position.y() + block_pixels.height() <= size.height();
+        let id = position.y() / block_size.height() * blocks_x + position.x() / block_size.width();
+        if !aligned || !inside || claimed[id].swap(true, Ordering::Relaxed) {
+            return Err(Error::Invalid("block position".into()));
+        }
+
+        for line in block.lines(&header.channels) {
+            let Some(slot) = slots[line.location.channel] else { continue };
+            let Vec2(x, y) = line.location.position;
+            if y < origin.y() || y >= origin.y() + extent.height() {
+                continue;
+            }
+
+            // The samples of the line within the region
+            let skip = origin.x().saturating_sub(x);
+            let end = (x + line.location.sample_count).min(origin.x() + extent.width());
+            let Some(count) = end.checked_sub(x + skip) else { continue };
+
+            let row = if hooks.flip_vertical { origin.y() + extent.height() - 1 - y } else { y - origin.y() };
+            let start = row * row_stride + (x + skip - origin.x()) * num_channels + slot;
+            let write = |i: usize, sample: T| {
+                let sample = if hooks.sanitize { sample.to_finite() } else { sample };
+                unsafe { output.write(start + i * num_channels, sample) }
+            };
+
+            match header.channels.list[line.location.channel].sample_type {
+                SampleType::F16 => for (i, value) in line.read_samples::<f16>().skip(skip).take(count).enumerate() { write(i, T::from_f16(value?)) },
+                SampleType::F32 => for (i, value) in line.read_samples::<f32>().skip(skip).take(count).enumerate() { write(i, T::from_f32(value?)) },
+                SampleType::U32 => for (i, value) in line.read_samples::<u32>().skip(skip).take(count).enumerate() { write(i, T::from_u32(value?)) },
+            }
+        }
+
+        decoded[id].store(true, Ordering::Relaxed);
+        Ok(())
+    };
+
+    chunks.par_bridge().try_for_each(|chunk| {
+        if hooks.is_cancelled() {
+            return Err(Error::Aborted);
+        }
+
+        // A block that cannot be decoded is left to be filled in when recovering
+        match decode_chunk(chunk) {
+            Err(_) if recover => {},
+            result => result?,
+        }
+
+        // Counted under the lock so the reported fractions only grow
+        if hooks.progress.is_some() {
+            let mut decoded_chunks = decoded_chunks.lock().unwrap_or_else(PoisonError::into_inner);
+            *decoded_chunks += 1;
+            hooks.report_progress(if *decoded_chunks == total_chunks { 1.0 } else { *decoded_chunks as f32 / total_chunks as f32 });
+        }
+
+        Ok(())
+    })?;
+
+    if let Some(missing_rows) = hooks.missing_rows {
+        let default_pixel = (0..num_channels)
+            .map(|slot| T::from_f32(hooks.default_pixel[slot.min(3)]))
+            .collect::<Vec<_>>();
+        let mut missing = vec![false; extent.height()];
+
+        for (id, _) in decoded.iter().enumerate().filter(|(_, decoded)| !decoded.load(Ordering::Relaxed)) {
+            let (block_x, block_y) = ((id % blocks_x) * block_size.width(), (id / blocks_x) * block_size.height());
+            let left = block_x.max(origin.x());
+            let right = (block_x + block_size.width()).min(size.width()).min(origin.x() + extent.width());
+            let top = block_y.max(origin.y());
+            let bottom = (block_y + block_size.height()).min(size.height()).min(origin.y() + extent.height());
+
+            for y in top..bottom.max(top) {
+                let row = if hooks.flip_vertical { origin.y() + extent.height() - 1 - y } else { y - origin.y() };
+                missing[row] |= left < right;
+                for x in left..right.max(left) {
+                    let start = row * row_stride + (x - origin.x()) * num_channels;
+                    flat_data[start..start + num_channels].copy_from_slice(&default_pixel);
+                }
+            }
+        }
+
+        missing_rows.store(missing.iter().filter(|&&missing| missing).count(), Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+// Optional callbacks of a load, shared by the decoding threads, whether decoded samples are made finite, which
+// part of the level is decoded, as the position and size of a rectangle within it, all of it if `None`, how many
+// samples apart the rows of the output start, 0 for tightly packed rows, whether the bottom row comes first,
+// whether the file is held to the specification strictly and, if `missing_rows` is given, whether blocks that cannot
+// be read are replaced with `default_pixel` instead of failing, counting the rows they touch
+#[derive(Clone, Copy, Default)]
+struct ReadHooks<'a> {
+    progress: Option<ExrProgressCallback>,
+    cancel: Option<&'a AtomicBool>,
+    sanitize: bool,
+    region: Option<(Vec2<usize>, Vec2<usize>)>,
+    row_stride: usize,
+    flip_vertical: bool,
+    pedantic: bool,
+    missing_rows: Option<&'a AtomicUsize>,
+    default_pixel: [f32; 4],
+}
+
+impl ReadHooks<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    fn report_progress(&self, fraction: f32) {
+        if let Some(progress) = self.progress {
+            progress(fraction);
+        }
+    }
+}
+
+// Samples that can be made finite, NaN becoming zero and infinities the largest value of the type
+trait FiniteSample: Copy {
+    fn to_finite(self) -> Self;
+}
+
+impl FiniteSample for f16 {
+    fn to_finite(self) -> Self {
+        if self.is_nan() { f16::ZERO } else { self.clamp(f16::MIN, f16::MAX) }
+    }
+}
+
+impl FiniteSample for f32 {
+    fn to_finite(self) -> Self {
+        if self.is_nan() { 0.0 } else { self.clamp(f32::MIN, f32::MAX) }
+    }
+}
+
+impl FiniteSample for u32 {
+    fn to_finite(self) -> Self {
+        self
+    }
+}
+
+// Swaps the rows of `row_len` samples of a layer decoded outside of `decode_layer_into` top to bottom
+fn flip_rows<T>(data: &mut [T], row_len: usize) {
+    let rows = data.len().checked_div(row_len).unwrap_or(0);
+    for y in 0..rows / 2 {
+        let (top, bottom) = data.split_at_mut((rows - 1 - y) * row_len);
+        top[y * row_len..][..row_len].swap_with_slice(&mut bottom[..row_len]);
+    }
+}
+
+// Makes the samples of a layer decoded outside of `decode_layer_into` finite
+unsafe fn sanitize_pixels(pixels: *mut c_void, len: usize, format: ExrPixelFormat) {
+    match format {
+        ExrPixelFormat::F16 => from_raw_parts_mut(pixels as *mut f16, len).iter_mut().for_each(|sample| *sample = sample.to_finite()),
+        ExrPixelFormat::F32 => from_raw_parts_mut(pixels as *mut f32, len).iter_mut().for_each(|sample| *sample = sample.to_finite()),
+        _ => {}
+    }
+}
+
+// The size of resolution `level` of the layer, if it has that level. Mip levels have the same index on both
+// axes, only rip maps are reduced along one axis alone.
+fn level_size(header: &Header, level: Vec2<usize>) -> Option<Vec2<usize>> {
+    match header.blocks {
+        BlockDescription::Tiles(tiles) if tiles.level_mode == LevelMode::MipMap => {
+            exr::meta::mip_map_levels(tiles.rounding_mode, header.layer_size)
+                .find(|(index, _)| Vec2(*index, *index) == level)
+                .map(|(_, size)| size)
+        },
+        BlockDescription::Tiles(tiles) if tiles.level_mode == LevelMode::RipMap => {
+            exr::meta::rip_map_levels(tiles.rounding_mode, header.layer_size)
+                .find(|(index, _)| *index == level)
+                .map(|(_, size)| size)
+        },
+        _ => (level == Vec2(0, 0)).then_some(header.layer_size),
+    }
+}
+
+// The interleaved output shared by the decoding threads
+struct SharedOutput<T>(*mut T);
+
+unsafe impl<T: Send> Sync for SharedOutput<T> {}
+
+impl<T> SharedOutput<T> {
+    // Safety: `index` must be in bounds and not written by any other thread
+    unsafe fn write(&self, index: usize, sample: T) {
+        self.0.add(index).write(sample);
+    }
+}
+
+// Decodes a layer interleaved in `order`. Layers mixing sample types, e.g. f16 colour with an f32 depth channel,
+// are widened to f32 as a whole.
+fn load_exr_layer(
+    source: ReadSource, layer_index: usize, level: Vec2<usize>, header: &Header, order: &[Option<usize>], hooks: ReadHooks
+) -> Result<(*mut c_void, ExrPixelFormat)> {
+    let size = level_size(header, level).ok_or_else(|| Error::Invalid("resolution level".into()))?;
+    let len = hooks.region.map_or(size, |(_, extent)| extent).area() * order.len();
+
+    Ok(match header.channels.uniform_sample_type {
+        Some(SampleType::F16) => (load_exr(source, layer_index, level, order, f16::ONE, hooks, len)?, ExrPixelFormat::F16),
+        Some(SampleType::F32) => (load_exr(source, layer_index, level, order, 1.0f32, hooks, len)?, ExrPixelFormat::F32),
+        Some(SampleType::U32) => (load_exr(source, layer_index, level, order, 1u32, hooks, len)?, ExrPixelFormat::U32),
+        None => (load_exr(source, layer_index, level, order, 1.0f32, hooks, len)?, ExrPixelFormat::F32),
+    })
+}
+
+// exr::Sample is sized for its largest variant, f32, which is why this decodes into the sample type of the file
+// instead, keeping F16 at 2 bytes per sample
+fn load_exr<T: FromNativeSample + FiniteSample + Send + Sync>(
+    source: ReadSource, layer_index: usize, level: Vec2<usize>, order: &[Option<usize>], one: T, hooks: ReadHooks, len: usize
+) -> Result<*mut c_void> {
+    let mut flat_data = vec![T::default(); len];
+    source.decode_into(layer_index, level, order, one, hooks, &mut flat_data)?;
+    Ok(into_raw(flat_data))
+}
+
+// A decoded layer as handed out by `load_from_path`
+struct LoadedLayer {
+    pixels: *mut c_void,
+    format: ExrPixelFormat,
+    num_channels: usize,
+    channel_names: String,
+    channel_formats: Vec<ExrPixelFormat>,
+    rgba: bool,
+    // How many layers the file holds in total
+    num_layers: usize,
+}
+
+// exr rejects subsampled channels, which luminance and chroma images use, so such layers are decoded here. Only
+// scan lines may be subsampled, and each block holds the lines of every channel in name order, leaving out the
+// lines and pixels a channel has no samples for. Chroma is upsampled by repeating each sample and turned back into
+// RGBA, other subsampled layers keep their channels. The offset tables are read from `tables_start` on, where the
+// already parsed headers of `meta` end.
+fn load_subsampled_layer(mut file: impl Read + Seek, meta: &MetaData, index: usize, tables_start: u64) -> Result<LoadedLayer> {
+    let header = &meta.headers[index];
+    let (position, size) = (header.own_attributes.layer_position, header.layer_size);
+
+    if header.deep || header.blocks != BlockDescription::ScanLines {
+        return Err(Error::NotSupported("subsampled tiles or deep data".into()));
+    }
+
+    if !matches!(header.compression, Compression::Uncompressed | Compression::RLE | Compression::ZIP1 | Compression::ZIP16) {
+        return Err(Error::NotSupported(format!("subsampled channels compressed with {:?}", header.compression).into()));
+    }
+
+    for channel in &header.channels.list {
+        let Vec2(x, y) = channel.sampling;
+        if x == 0 || y == 0 || position.x() % x as i32 != 0 || position.y() % y as i32 != 0 || !size.width().is_multiple_of(x) || !size.height().is_multiple_of(y) {
+            return Err(Error::Invalid("channel sampling".into()));
+        }
+    }
+
+    file.seek(SeekFrom::Start(tables_start))?;
+    let offsets = MetaData::read_offset_tables(&mut PeekRead::new(&mut file), &meta.headers)?.swap_remove(index);
+
+    let lines_per_block = header.compression.scan_lines_per_block();
+    // Held as f64 so that U32 samples above 2^24 survive exactly
+    let mut planes = header.channels.list.iter()
+        .map(|channel| vec![0.0f64; size.width() / channel.sampling.x() * (size.height() / channel.sampling.y())])
+        .collect::<Vec<_>>();
+
+    for offset in offsets {
+        file.seek(SeekFrom::Start(offset))?;
+        if meta.requirements.is_multilayer() {
+            i32::read_le(&mut file)?;
+        }
+
+        let first_line = i32::read_le(&mut file)? - position.y();
+        let byte_size = i32::read_le(&mut file)?;
+        if first_line < 0 || first_line as usize >= size.height() || !(first_line as usize).is_multiple_of(lines_per_block) {
+            return Err(Error::Invalid("block position".into()));
+        }
+
+        let first_line = first_line as usize;
+        let lines = first_line..size.height().min(first_line + lines_per_block);
+        let samples_per_line = |line: usize| header.channels.list.iter()
+            .filter(move |channel| line.is_multiple_of(channel.sampling.y()))
+            .map(|channel| size.width() / channel.sampling.x() * channel.sample_type.bytes_per_sample())
+            .sum::<usize>();
+
+        // Blocks that would not get smaller are stored as they are
+        let raw_size = lines.clone().map(samples_per_line).sum::<usize>();
+        if byte_size < 0 || byte_size as usize > raw_size {
+            return Err(Error::Invalid("block size".into()));
+        }
+
+        let mut compressed = vec![0; byte_size as usize];
+        file.read_exact(&mut compressed)?;
+        let bytes = if compressed.len() == raw_size { compressed } else { decompress_subsampled(header.compression, compressed, raw_size)? };
+
+        let mut bytes = bytes.as_slice();
+        for line in lines {
+            for (channel, plane) in header.channels.list.iter().zip(&mut planes) {
+                let Vec2(x, y) = channel.sampling;
+                if !line.is_multiple_of(y) {
+                    continue;
+                }
+
+                let row_len = size.width() / x;
+                for sample in &mut plane[line / y * row_len..][..row_len] {
+                    let (value, rest) = bytes.split_at(channel.sample_type.bytes_per_sample());
+                    bytes = rest;
+
+                    *sample = match channel.sample_type {
+                        SampleType::F16 => f16::from_le_bytes([value[0], value[1]]).to_f64(),
+                        SampleType::F32 => f32::from_le_bytes([value[0], value[1], value[2], value[3]]) as f64,
+                        SampleType::U32 => u32::from_le_bytes([value[0], value[1], value[2], value[3]]) as f64,
+                    };
+                }
+            }
+        }
+    }
+
+    let channels = &header.channels.list;
+    let sample = |channel: usize, x: usize, y: usize| {
+        let Vec2(sampling_x, sampling_y) = channels[channel].sampling;
+        planes[channel][y / sampling_y * (size.width() / sampling_x) + x / sampling_x]
+    };
+    let find = |name: &str| header.channels.find_index_of_channel(&Text::from(name));
+    let pixels = (0..size.height()).flat_map(|y| (0..size.width()).map(move |x| (x, y)));
+
+    let (values, layer) = match (find("Y"), find("RY"), find("BY")) {
+        (Some(luminance), Some(red), Some(blue)) => {
+            let alpha = find("A");
+            let [red_weight, green_weight, blue_weight] = LUMINANCE_WEIGHTS.map(f64::from);
+
+            let values = pixels.flat_map(|(x, y)| {
+                let luminance = sample(luminance, x, y);
+                let red = (sample(red, x, y) + 1.0) * luminance;
+                let blue = (sample(blue, x, y) + 1.0) * luminance;
+                let green = (luminance - red_weight * red - blue_weight * blue) / green_weight;
+                [red, green, blue, alpha.map_or(1.0, |alpha| sample(alpha, x, y))]
+            }).collect::<Vec<_>>();
+
+            let mut channel_formats = vec![ExrPixelFormat::Unknown; 3];
+            channel_formats.push(alpha.map_or(ExrPixelFormat::Unknown, |alpha| channels[alpha].sample_type.into()));
+
+            (values, LoadedLayer {
+                pixels: std::ptr::null_mut(),
+                format: ExrPixelFormat::F32,
+                num_channels: 4,
+                channel_names: "R\nG\nB\nA".to_string(),
+                channel_formats,
+                rgba: alpha.is_some(),
+                num_layers: meta.headers.len(),
+            })
+        },
+        _ => {
+            let values = pixels.flat_map(|(x, y)| (0..channels.len()).map(move |channel| sample(channel, x, y))).collect();
+
+            (values, LoadedLayer {
+                pixels: std::ptr::null_mut(),
+                format: ExrPixelFormat::F32,
+                num_channels: channels.len(),
+                channel_names: channels.iter().map(|channel| channel.name.to_string()).collect::<Vec<_>>().join("\n"),
+                channel_formats: channels.iter().map(|channel| channel.sample_type.into()).collect(),
+                rgba: false,
+                num_layers: meta.headers.len(),
+            })
+        },
+    };
+
+    let layer = match header.channels.uniform_sample_type {
+        Some(SampleType::F16) => LoadedLayer {
+            pixels: into_raw(values.iter().map(|&value| f16::from_f64(value)).collect::<Vec<_>>()),
+            format: ExrPixelFormat::F16,
+            ..layer
+        },
+        Some(SampleType::U32) if layer.channel_formats.iter().all(|&format| format == ExrPixelFormat::U32) => LoadedLayer {
+            pixels: into_raw(values.iter().map(|&value| value as u32).collect::<Vec<_>>()),
+            format: ExrPixelFormat::U32,
+            ..layer
+        },
+        _ => LoadedLayer { pixels: into_raw(values.iter().map(|&value| value as f32).collect::<Vec<_>>()), ..layer },
+    };
+
+    Ok(layer)
+}
+
+// Only scan line layers may subsample channels, which exr does not decode itself
+fn is_subsampled(header: &Header) -> bool {
+    header.channels.list.iter().any(|channel| channel.sampling != Vec2(1, 1))
+}
+
+// The header of the layer called `layer_name`, or the first one without a name
+fn header_index(headers: &[Header], layer_name: Option<&str>) -> Option<usize> {
+    match layer_name {
+        Some(name) => headers.iter().position(|header| {
+            header.own_attributes.layer_name.as_ref().is_some_and(|layer_name| layer_name.eq(name))
+        }),
+        None => Some(0),
+    }
+}
+
+// exr reads the headers and chunks of deep layers but cannot decompress them, so they are decoded here. Each block
+// starts with a table of the cumulative sample count of every pixel, restarting with each line, and stores the
+// samples of a line channel by channel, all samples of a pixel following each other. Returns `None` if there is no
+// such layer.
+fn flatten_deep_layer(path: &str, layer_name: Option<&str>) -> Result<Option<(Header, Vec<f32>)>> {
+    let mut read = PeekRead::new(BufReader::new(File::open(path)?));
+    exr::meta::magic_number::validate_exr(&mut read)?;
+    let requirements = Requirements::read(&mut read)?;
+    requirements.validate()?;
+    let headers = Header::read_all(&mut read, &requirements, false)?;
+
+    let Some(index) = header_index(&headers, layer_name) else {
+        return Ok(None);
+    };
+
+    let header = headers[index].clone();
+    let (position, size) = (header.own_attributes.layer_position, header.layer_size);
+
+    if !header.deep || header.blocks != BlockDescription::ScanLines {
+        return Err(Error::NotSupported("flat or deep tiled layers".into()));
+    }
+
+    if size.area() == 0 {
+        return Err(Error::Invalid("data window".into()));
+    }
+
+    if !matches!(header.compression, Compression::Uncompressed | Compression::RLE | Compression::ZIP1 | Compression::ZIP16) {
+        return Err(Error::NotSupported(format!("deep data compressed with {:?}", header.compression).into()));
+    }
+
+    let channels = &header.channels.list;
+    let find = |name: &str| header.channels.find_index_of_channel(&Text::from(name));
+    let (depth, colors, alpha) = (find("Z"), [find("R"), find("G"), find("B")], find("A"));
+    let bytes_per_sample = channels.iter().map(|channel| channel.sample_type.bytes_per_sample()).sum::<usize>();
+
+    let offsets = MetaData::read_offset_tables(&mut read, &headers)?.swap_remove(index);
+    let mut file = BufReader::new(File::open(path)?);
+
+    let lines_per_block = header.compression.scan_lines_per_block();
+    let mut pixels = vec![0.0f32; size.area() * 4];
+
+    for offset in offsets {
+        file.seek(SeekFrom::Start(offset))?;
+        if requirements.is_multilayer() {
+            i32::read_le(&mut file)?;
+        }
+
+        let first_line = i32::read_le(&mut file)? - position.y();
+        if first_line < 0 || first_line as usize >= size.height() || !(first_line as usize).is_multiple_of(lines_per_block) {
+            return Err(Error::Invalid("block position".into()));
+        }
+
+        let first_line = first_line as usize;
+        let lines = first_line..size.height().min(first_line + lines_per_block);
+        let table_size = u64::read_le(&mut file)? as usize;
+        let compressed_size = u64::read_le(&mut file)? as usize;
+        let raw_size = u64::read_le(&mut file)? as usize;
+
+        // Tables and samples that would not get smaller are stored as they are
+        let raw_table_size = lines.len() * size.width() * 4;
+        if table_size > raw_table_size || compressed_size > raw_size {
+            return Err(Error::Invalid("block size".into()));
+        }
+
+        let mut table = vec![0; table_size];
+        file.read_exact(&mut table)?;
+        let table = if table.len() == raw_table_size { table } else { decompress_subsampled(header.compression, table, raw_table_size)? };
+        let cumulative_counts = table.chunks_exact(4).map(|bytes| i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+
+        // Where the samples of each pixel start within its line, and how many samples each line holds
+        let mut starts = Vec::with_capacity(lines.len() * size.width());
+        let mut line_counts = Vec::with_capacity(lines.len());
+        for line_table in cumulative_counts.collect::<Vec<_>>().chunks_exact(size.width()) {
+            let mut previous = 0;
+            for &count in line_table {
+                if count < previous {
+                    return Err(Error::Invalid("deep sample count".into()));
+                }
+
+                starts.push(previous as usize);
+                previous = count;
+            }
+
+            line_counts.push(previous as usize);
+        }
+
+        if line_counts.iter().sum::<usize>() * bytes_per_sample != raw_size {
+            return Err(Error::Invalid("deep sample data size".into()));
+        }
+
+        let mut compressed = vec![0; compressed_size];
+        file.read_exact(&mut compressed)?;
+        let bytes = if compressed.len() == raw_size { compressed } else { decompress_subsampled(header.compression, compressed, raw_size)? };
+
+        let mut line_start = 0;
+        for (line_index, (line, &line_count)) in lines.zip(&line_counts).enumerate() {
+            // The samples of each channel within the line
+            let mut channel_start = line_start;
+            let channel_starts = channels.iter().map(|channel| {
+                let start = channel_start;
+                channel_start += line_count * channel.sample_type.bytes_per_sample();
+                start
+            }).collect::<Vec<_>>();
+            line_start = channel_start;
+
+            let sample = |channel: usize, index: usize| {
+                let sample_type = channels[channel].sample_type;
+                let bytes = &bytes[channel_starts[channel] + index * sample_type.bytes_per_sample()..];
+                match sample_type {
+                    SampleType::F16 => f16::from_le_bytes([bytes[0], bytes[1]]).to_f32(),
+                    SampleType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                    SampleType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+                }
+            };
+
+            for x in 0..size.width() {
+                let index = line_index * size.width() + x;
+                let (start, end) = (starts[index], if x + 1 < size.width() { starts[index + 1] } else { line_count });
+                if start == end {
+                    continue;
+                }
+
+                let front = match depth {
+                    Some(depth) => (start..end).min_by(|&a, &b| sample(depth, a).total_cmp(&sample(depth, b))).unwrap_or(start),
+                    None => start,
+                };
+
+                let pixel = &mut pixels[(line * size.width() + x) * 4..][..4];
+                for (value, color) in pixel.iter_mut().zip(colors) {
+                    *value = color.map_or(0.0, |color| sample(color, front));
+                }
+                pixel[3] = alpha.map_or(1.0, |alpha| sample(alpha, front));
+            }
+        }
+    }
+
+    Ok(Some((header, pixels)))
+}
+
+// exr sizes a block by its full resolution channels, so the `raw_size` bytes of a subsampled block are described
+// as a single line of F16 samples instead. The bytes are kept in file order, which is little endian.
+fn decompress_subsampled(compression: Compression, compressed: Vec<u8>, raw_size: usize) -> Result<Vec<u8>> {
+    let channel = ChannelDescription {
+        name: Text::from("raw"),
+        sample_type: SampleType::F16,
+        quantize_linearly: false,
+        sampling: Vec2(1, 1),
+    };
+
+    let size = Vec2(raw_size / 2, 1);
+    let header = Header::new(Text::from(DEFAULT_LAYER_NAME), size, std::iter::once(channel).collect())
+        .with_encoding(compression, BlockDescription::ScanLines, LineOrder::Increasing);
+
+    compression.decompress_image_section_from_le(&header, compressed, IntegerBounds::new(Vec2(0, 0), size), false)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FileKind {
+    Exr,
+    Radiance,
+    Pfm,
+}
+
+// Tells the format by the first bytes of the file, and by its extension for files starting like none of them or
+// that cannot be read, which then fail to open as usual
+fn file_kind(path: &str) -> FileKind {
+    let mut start = Vec::with_capacity(10);
+    if let Ok(file) = File::open(path) {
+        let _ = file.take(10).read_to_end(&mut start);
+    }
+
+    if start.starts_with(&exr::meta::magic_number::BYTES) {
+        FileKind::Exr
+    } else if start.starts_with(b"#?RADIANCE") || start.starts_with(b"#?RGBE") {
+        FileKind::Radiance
+    } else if (start.starts_with(b"PF") || start.starts_with(b"Pf")) && start.get(2).is_some_and(u8::is_ascii_whitespace) {
+        FileKind::Pfm
+    } else {
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("hdr") => FileKind::Radiance,
+            Some("pfm") => FileKind::Pfm,
+            _ => FileKind::Exr,
+        }
+    }
+}
+
+// radiant only knows the "#?RADIANCE" signature, so the older "#?RGBE" one is swapped for it
+fn open_radiance(path: &str) -> std::io::Result<impl BufRead> {
+    let mut read = BufReader::new(File::open(path)?);
+    let rgbe = read.fill_buf()?.starts_with(b"#?RGBE");
+    if rgbe {
+        read.consume(b"#?RGBE".len());
+    }
+
+    let signature: &[u8] = if rgbe { b"#?RADIANCE" } else { b"" };
+    Ok(signature.chain(read))
+}
+
+// A Portable Float Map, with rows reordered top to bottom like EXR pixels
+struct PfmImage {
+    width: usize,
+    height: usize,
+    num_channels: usize,
+    data: Vec<f32>,
+}
+
+impl PfmImage {
+    fn format(&self) -> ExrPixelFormat {
+        if self.num_channels == 3 { ExrPixelFormat::RGBF32 } else { ExrPixelFormat::F32 }
+    }
+}
+
+// The width, height, channel count and scale
+fn read_pfm_header(read: &mut impl BufRead) -> Result<(usize, usize, usize, f32)> {
+    let num_channels = match pfm_token(read)?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(Error::Invalid("pfm magic number".into())),
+    };
+
+    let invalid = |name: &'static str| Error::Invalid(name.into());
+    let width = pfm_token(read)?.parse().map_err(|_| invalid("pfm width"))?;
+    let height = pfm_token(read)?.parse().map_err(|_| invalid("pfm height"))?;
+    let scale = pfm_token(read)?.parse().map_err(|_| invalid("pfm scale"))?;
+    Ok((width, height, num_channels, scale))
+}
+
+// "PF" is followed by RGB samples and "Pf" by grey ones. A negative scale marks little endian samples,
+// and rows are stored from the bottom up.
+fn load_pfm(mut read: impl BufRead) -> Result<PfmImage> {
+    let (width, height, num_channels, scale) = read_pfm_header(&mut read)?;
+
+    let invalid = |name: &'static str| Error::Invalid(name.into());
+    let row_len = width.checked_mul(num_channels).ok_or_else(|| invalid("pfm width"))?;
+    let len = row_len.checked_mul(height).ok_or_else(|| invalid("pfm height"))?;
+
+    let mut bytes = vec![0_u8; len * mem::size_of::<f32>()];
+    read.read_exact(&mut bytes)?;
+
+    let samples = bytes.chunks_exact(mem::size_of::<f32>()).map(|sample| {
+        let sample = [sample[0], sample[1], sample[2], sample[3]];
+        if scale < 0.0 { f32::from_le_bytes(sample) } else { f32::from_be_bytes(sample) }
+    }).collect::<Vec<f32>>();
+
+    let data = if row_len == 0 { samples } else { samples.chunks_exact(row_len).rev().flatten().copied().collect() };
+    Ok(PfmImage { width, height, num_channels, data })
+}
+
+// Header fields are separated by whitespace, the single one after the scale being consumed with it
+fn pfm_token(read: &mut impl BufRead) -> Result<String> {
+    let mut token = String::new();
+
+    for byte in read.bytes() {
+        let byte = byte?;
+
+        if byte.is_ascii_whitespace() {
+            if token.is_empty() { continue } else { break }
+        }
+
+        token.push(byte as char);
+        if token.len() > 32 {
+            return Err(Error::Invalid("pfm header".into()));
+        }
+    }
+
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file in the temporary directory, named after the test so that tests running in parallel never share one
+    fn temp_path(name: &str) -> CString {
+        CString::new(std::env::temp_dir().join(format!("vl_openexr_{}", name)).to_str().unwrap()).unwrap()
+    }
+
+    fn write<T>(path: &CStr, width: usize, height: usize, num_channels: u32, input_format: ExrPixelFormat, options: &ExrWriteOptions, data: &[T]) -> ExrStatus {
+        unsafe {
+            write_texture(path.as_ptr(), width as i32, height as i32, num_channels, std::ptr::null(), input_format, options, data.as_ptr() as *const c_void)
+        }
+    }
+
+    struct Loaded {
+        width: usize,
+        height: usize,
+        num_channels: usize,
+        format: ExrPixelFormat,
+        pixels: *mut c_void,
+    }
+
+    impl Loaded {
+        fn samples<T: Copy>(&self) -> Vec<T> {
+            unsafe { from_raw_parts(self.pixels as *const T, self.width * self.height * self.num_channels) }.to_vec()
+        }
+    }
+
+    impl Drop for Loaded {
+        fn drop(&mut self) {
+            unsafe { free_texture(self.pixels, self.width as u32, self.height as u32, self.num_channels as u32, self.format) };
+        }
+    }
+
+    fn load(path: &CStr, options: &ExrLoadOptions) -> Loaded {
+        let (mut width, mut height, mut num_channels, mut format) = (0, 0, 0, ExrPixelFormat::Unknown);
+        let mut pixels = std::ptr::null_mut();
+        let status = unsafe {
+            load_from_path(
+                path.as_ptr(), std::ptr::null(), options, &mut width, &mut height, &mut num_channels, &mut format, std::ptr::null_mut(),
+                std::ptr::null_mut(), 0, std::ptr::null_mut(), &mut pixels
+            )
+        };
+        assert_eq!(status, ExrStatus::Ok);
+
+        Loaded { width: width as usize, height: height as usize, num_channels: num_channels as usize, format, pixels }
+    }
+
+    fn gradient(width: usize, height: usize, num_channels: usize) -> Vec<f32> {
+        (0..width * height * num_channels).map(|index| (index % 251) as f32 / 250.0).collect()
+    }
+
+    #[test]
+    fn preview_of_rgb_and_rgba() {
+        for num_channels in [3, 4] {
+            let path = temp_path(&format!("preview_{}.exr", num_channels));
+            let mut data = gradient(64, 32, num_channels);
+            data[0] = f32::NAN;
+            let default_pixel = [1.0; 4];
+            let options = ExrWriteOptions { preview_size: 16, default_pixel: &default_pixel, ..Default::default() };
+            assert_eq!(write(&path, 64, 32, num_channels as u32, ExrPixelFormat::F32, &options, &data), ExrStatus::Ok);
+
+            let (mut width, mut height, mut rgba) = (0, 0, std::ptr::null_mut());
+            assert_eq!(unsafe { read_preview(path.as_ptr(), &mut width, &mut height, &mut rgba) }, ExrStatus::Ok);
+            let pixels = unsafe { from_raw_parts(rgba, width as usize * height as usize * 4) }.to_vec();
+            unsafe { free_preview(rgba, width as u32, height as u32) };
+
+            data[0] = 1.0;
+            let expected = preview_image(&data, Vec2(64, 32), num_channels, |y| y * 64 * num_channels, 16);
+            assert_eq!((width, height), (16, 8));
+            assert_eq!(pixels, expected.pixel_data.iter().map(|&value| value as u8).collect::<Vec<_>>());
+            assert!(num_channels == 4 || pixels.chunks_exact(4).all(|pixel| pixel[3] == u8::MAX));
+            assert_eq!(load(&path, &ExrLoadOptions::default()).samples::<f32>()[0], 1.0);
+        }
+    }
+
+    #[test]
+    fn b44_keeps_f32_exact() {
+        let data = gradient(70, 45, 4).iter().map(|sample| sample * 1000.0 + 1.0 / 3.0).collect::<Vec<_>>();
+        for encoding in [ExrEncoding::B44, ExrEncoding::B44A] {
+            let path = temp_path(&format!("b44_f32_{:?}.exr", encoding));
+            let options = ExrWriteOptions { encoding, ..Default::default() };
+            assert_eq!(write(&path, 70, 45, 4, ExrPixelFormat::F32, &options, &data), ExrStatus::Ok);
+
+            let loaded = load(&path, &ExrLoadOptions::default());
+            assert_eq!(loaded.format, ExrPixelFormat::F32);
+            assert_eq!(loaded.samples::<f32>(), data);
+        }
+    }
+
+    #[test]
+    fn b44_f16_round_trip() {
+        // A smooth ramp, as B44 spends its 6 bit differences within 4x4 blocks, with an opaque alpha
+        let data = (0..45).flat_map(|y| (0..70).flat_map(move |x| {
+            let ramp = (x + y) as f32 / 115.0;
+            [ramp, ramp * 0.5, 1.0 - ramp, 1.0].map(f16::from_f32)
+        })).collect::<Vec<_>>();
+        for encoding in [ExrEncoding::B44, ExrEncoding::B44A] {
+            let path = temp_path(&format!("b44_f16_{:?}.exr", encoding));
+            let options = ExrWriteOptions { encoding, ..Default::default() };
+            assert_eq!(write(&path, 70, 45, 4, ExrPixelFormat::F16, &options, &data), ExrStatus::Ok);
+
+            let loaded = load(&path, &ExrLoadOptions::default());
+            assert_eq!(loaded.format, ExrPixelFormat::F16);
+            let samples = loaded.samples::<f16>();
+            for (index, (sample, expected)) in samples.iter().zip(&data).enumerate() {
+                assert!((sample.to_f32() - expected.to_f32()).abs() < 0.01, "{:?} sample {}: {} for {}", encoding, index, sample, expected);
+            }
+            if encoding == ExrEncoding::B44A {
+                assert!(samples.chunks_exact(4).all(|pixel| pixel[3] == f16::ONE), "{:?}", encoding);
+            }
+        }
+    }
+
+    #[test]
+    fn dwab_gradient() {
+        let path = temp_path("dwab.exr");
+        let data = gradient(67, 45, 4);
+        let options = ExrWriteOptions { encoding: ExrEncoding::DWAB, ..Default::default() };
+        assert_eq!(write(&path, 67, 45, 4, ExrPixelFormat::F32, &options, &data), ExrStatus::Ok);
+
+        let loaded = load(&path, &ExrLoadOptions::default());
+        assert_eq!((loaded.width, loaded.height, loaded.num_channels), (67, 45, 4));
+    }
+
+    #[test]
+    fn layer_name_in_header() {
+        let path = temp_path("layer_name.exr");
+        let layer_name = CString::new("beauty").unwrap();
+        let options = ExrWriteOptions { layer_name: layer_name.as_ptr(), ..Default::default() };
+        assert_eq!(write(&path, 8, 4, 4, ExrPixelFormat::F32, &options, &gradient(8, 4, 4)), ExrStatus::Ok);
+
+        let meta = MetaData::read_from_file(path.to_str().unwrap(), false).unwrap();
+        assert_eq!(meta.headers[0].own_attributes.layer_name, Some(Text::from("beauty")));
+    }
+
+    #[test]
+    fn pxr24_rounds_f32_to_24_bits() {
+        let path = temp_path("pxr24.exr");
+        let data = gradient(70, 45, 4).iter().map(|sample| sample * 1000.0 + 1.0 / 3.0).collect::<Vec<_>>();
+        let options = ExrWriteOptions { encoding: ExrEncoding::PXR24, ..Default::default() };
+        assert_eq!(write(&path, 70, 45, 4, ExrPixelFormat::F32, &options, &data), ExrStatus::Ok);
+
+        let loaded = load(&path, &ExrLoadOptions::default());
+        assert_eq!(loaded.format, ExrPixelFormat::F32);
+        for (stored, written) in loaded.samples::<f32>().into_iter().zip(data) {
+            assert!((stored - written).abs() <= written.abs() * 2.0f32.powi(-15), "{} stored as {}", written, stored);
+        }
+    }
+
+    #[test]
+    fn metadata_in_header() {
+        let path = temp_path("metadata.exr");
+        let owner = CString::new("lighting").unwrap();
+        let comments = CString::new("v002").unwrap();
+        let capture_date = CString::new("2024:05:17 10:30:00").unwrap();
+        let options = ExrWriteOptions { owner: owner.as_ptr(), comments: comments.as_ptr(), capture_date: capture_date.as_ptr(), ..Default::default() };
+        assert_eq!(write(&path, 8, 4, 4, ExrPixelFormat::F32, &options, &gradient(8, 4, 4)), ExrStatus::Ok);
+
+        let meta = MetaData::read_from_file(path.to_str().unwrap(), false).unwrap();
+        let attributes = &meta.headers[0].own_attributes;
+        assert_eq!(attributes.owner, Some(Text::from("lighting")));
+        assert_eq!(attributes.comments, Some(Text::from("v002")));
+        assert_eq!(attributes.capture_date, Some(Text::from("2024:05:17 10:30:00")));
+    }
+
+    #[test]
+    fn parallel_decode_matches_serial() {
+        let data = gradient(257, 131, 4);
+        let layouts = [("scan_lines", BLOCK_MODE_SCAN_LINES, 0), ("tiles", BLOCK_MODE_TILES, 32)];
+        for (name, block_mode, tile_size) in layouts {
+            let path = temp_path(&format!("parallel_{}.exr", name));
+            let options = ExrWriteOptions { encoding: ExrEncoding::ZIP16, block_mode, tile_width: tile_size, tile_height: tile_size, ..Default::default() };
+            assert_eq!(write(&path, 257, 131, 4, ExrPixelFormat::F32, &options, &data), ExrStatus::Ok);
+
+            let serial_pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+            let serial = serial_pool.install(|| load(&path, &ExrLoadOptions::default()).samples::<f32>());
+            let parallel = load(&path, &ExrLoadOptions::default()).samples::<f32>();
+            assert_eq!(serial, data);
+            assert_eq!(parallel, data);
+        }
+    }
+
+    #[test]
+    fn sanitize_non_finite_samples() {
+        let path = temp_path("sanitize.exr");
+        let mut data = gradient(8, 4, 4);
+        data[..3].copy_from_slice(&[f32::NAN, f32::INFINITY, f32::NEG_INFINITY]);
+        assert_eq!(write(&path, 8, 4, 4, ExrPixelFormat::F32, &ExrWriteOptions::default(), &data), ExrStatus::Ok);
+
+        let raw = load(&path, &ExrLoadOptions::default()).samples::<f32>();
+        assert!(raw[0].is_nan() && raw[1] == f32::INFINITY && raw[2] == f32::NEG_INFINITY);
+
+        let sanitized = load(&path, &ExrLoadOptions { sanitize: true, ..Default::default() }).samples::<f32>();
+        assert_eq!(sanitized[..3], [0.0, f32::MAX, f32::MIN]);
+        assert_eq!(sanitized[3..], data[3..]);
+    }
+
+    #[test]
+    fn decreasing_tiles_keep_pixels() {
+        let path = temp_path("decreasing_tiles.exr");
+        let data = gradient(70, 45, 4);
+        let options = ExrWriteOptions {
+            encoding: ExrEncoding::ZIP16, block_mode: BLOCK_MODE_TILES, tile_width: 16, tile_height: 16, line_order: LINE_ORDER_DECREASING,
+            ..Default::default()
+        };
+        assert_eq!(write(&path, 70, 45, 4, ExrPixelFormat::F32, &options, &data), ExrStatus::Ok);
+        assert_eq!(load(&path, &ExrLoadOptions::default()).samples::<f32>(), data);
+
+        let image = read_all_data_from_file(path.to_str().unwrap()).unwrap();
+        let layer = &image.layer_data[0];
+        assert_eq!(layer.encoding.line_order, LineOrder::Decreasing);
+        assert_eq!(layer.encoding.blocks, Blocks::Tiles(Vec2(16, 16)));
+
+        // Channels are stored in name order, A, B, G and R
+        for (channel, offset) in layer.channel_data.list.iter().zip([3, 2, 1, 0]) {
+            let Levels::Singular(FlatSamples::F32(samples)) = &channel.sample_data else { panic!("{} is not F32", channel.name) };
+            assert_eq!(*samples, data.iter().skip(offset).step_by(4).copied().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn quantize_rounds_to_nearest_even() {
+        let mut samples = [1.0 + 2.0f32.powi(-20), 1.0 + 2.0f32.powi(-16), 1.0 + 3.0 * 2.0f32.powi(-16), 1.0 / 3.0, f32::INFINITY, f32::NAN];
+        assert_eq!(unsafe { quantize_samples(samples.as_mut_ptr() as *mut c_void, samples.len(), ExrPixelFormat::F32, 15) }, ExrStatus::Ok);
+        assert_eq!(samples[..3], [1.0, 1.0, 1.0 + 2.0f32.powi(-14)]);
+        assert_eq!(samples[3].to_bits() & 0xff, 0);
+        assert!((samples[3] - 1.0 / 3.0).abs() <= 2.0f32.powi(-17));
+        assert!(samples[4] == f32::INFINITY && samples[5].is_nan());
+
+        let mut halves = gradient(16, 1, 1).into_iter().map(f16::from_f32).collect::<Vec<_>>();
+        assert_eq!(unsafe { quantize_samples(halves.as_mut_ptr() as *mut c_void, halves.len(), ExrPixelFormat::F16, 5) }, ExrStatus::Ok);
+        assert!(halves.iter().all(|sample| sample.to_bits() & 0x1f == 0));
+
+        assert_eq!(unsafe { quantize_samples(halves.as_mut_ptr() as *mut c_void, halves.len(), ExrPixelFormat::F16, 11) }, ExrStatus::InvalidArgument);
+        assert_eq!(unsafe { quantize_samples(halves.as_mut_ptr() as *mut c_void, halves.len(), ExrPixelFormat::U32, 8) }, ExrStatus::Unsupported);
+    }
+
+    #[test]
+    fn u32_above_2_pow_24_is_exact() {
+        let path = temp_path("u32_ids.exr");
+        let data = (0..64 * 16).map(|index| (1u32 << 24) + 1 + index * 40503).chain([u32::MAX, (1 << 24) + 1, 0]).collect::<Vec<_>>();
+        assert_eq!(write(&path, data.len(), 1, 1, ExrPixelFormat::U32, &ExrWriteOptions::default(), &data), ExrStatus::Ok);
+
+        let loaded = load(&path, &ExrLoadOptions::default());
+        assert_eq!(loaded.format, ExrPixelFormat::U32);
+        assert_eq!(loaded.samples::<u32>(), data);
+    }
+
+    #[test]
+    fn multilayer_part_names() {
+        let path = temp_path("multilayer.exr");
+        let data = gradient(8, 4, 4);
+        let write_parts = |names: &[&str]| {
+            let names = names.iter().map(|name| CString::new(*name).unwrap()).collect::<Vec<_>>();
+            let name_pointers = names.iter().map(|name| name.as_ptr()).collect::<Vec<_>>();
+            let formats = vec![ExrPixelFormat::F32; names.len()];
+            let datas = vec![data.as_ptr() as *const c_void; names.len()];
+            unsafe { write_multilayer(path.as_ptr(), 8, 4, ExrEncoding::ZIP16, names.len() as u32, name_pointers.as_ptr(), formats.as_ptr(), datas.as_ptr()) }
+        };
+
+        let _ = std::fs::remove_file(path.to_str().unwrap());
+        assert_eq!(write_parts(&["beauty", "", "depth"]), ExrStatus::InvalidArgument);
+        assert_eq!(write_parts(&["beauty", "depth", "beauty"]), ExrStatus::InvalidArgument);
+        assert!(!Path::new(path.to_str().unwrap()).exists());
+
+        assert_eq!(write_parts(&["beauty", "normals", "depth"]), ExrStatus::Ok);
+        let meta = MetaData::read_from_file(path.to_str().unwrap(), false).unwrap();
+        let names = meta.headers.iter().map(|header| header.own_attributes.layer_name.as_ref().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(names, ["beauty", "normals", "depth"]);
+    }
+
+    #[test]
+    fn partial_tiles_match_scan_lines() {
+        let data = gradient(70, 45, 4);
+        let layouts = [("scan_lines", BLOCK_MODE_SCAN_LINES, 0, 0), ("tiles_16x16", BLOCK_MODE_TILES, 16, 16), ("tiles_7x5", BLOCK_MODE_TILES, 7, 5)];
+        for (name, block_mode, tile_width, tile_height) in layouts {
+            let path = temp_path(&format!("partial_{}.exr", name));
+            let options = ExrWriteOptions { encoding: ExrEncoding::ZIP16, block_mode, tile_width, tile_height, ..Default::default() };
+            assert_eq!(write(&path, 70, 45, 4, ExrPixelFormat::F32, &options, &data), ExrStatus::Ok);
+
+            let meta = MetaData::read_from_file(path.to_str().unwrap(), false).unwrap();
+            let tile_size = match meta.headers[0].blocks {
+                BlockDescription::Tiles(tiles) => Some(tiles.tile_size),
+                BlockDescription::ScanLines => None,
+            };
+            assert_eq!(tile_size, (block_mode == BLOCK_MODE_TILES).then_some(Vec2(tile_width as usize, tile_height as usize)));
+            assert_eq!(load(&path, &ExrLoadOptions::default()).samples::<f32>(), data, "{}", name);
+        }
+    }
+
+    #[test]
+    fn degenerate_sizes() {
+        let path = temp_path("degenerate.exr");
+        let data = gradient(4, 4, 4);
+        let _ = std::fs::remove_file(path.to_str().unwrap());
+        for (width, height) in [(0, 4), (4, 0), (0, 0), (-3, 2)] {
+            let status = unsafe {
+                write_texture(path.as_ptr(), width, height, 4, std::ptr::null(), ExrPixelFormat::F32, std::ptr::null(), data.as_ptr() as *const c_void)
+            };
+            assert_eq!(status, ExrStatus::InvalidArgument, "{}x{}", width, height);
+        }
+        assert!(!Path::new(path.to_str().unwrap()).exists());
+
+        for (width, height) in [(1, 1), (1, 37), (37, 1)] {
+            for num_channels in [1, 4] {
+                let path = temp_path(&format!("degenerate_{}x{}_{}.exr", width, height, num_channels));
+                let data = (0..width * height * num_channels).map(|index| index as f32).collect::<Vec<_>>();
+                assert_eq!(write(&path, width, height, num_channels as u32, ExrPixelFormat::F32, &ExrWriteOptions::default(), &data), ExrStatus::Ok);
+
+                let loaded = load(&path, &ExrLoadOptions::default());
+                assert_eq!((loaded.width, loaded.height, loaded.num_channels), (width, height, num_channels));
+                assert_eq!(loaded.samples::<f32>(), data, "{}x{} with {} channels", width, height, num_channels);
+            }
+        }
+    }
+
+    #[test]
+    fn widen_f16_matches_scalar() {
+        let samples = (0..=u16::MAX).map(f16::from_bits).collect::<Vec<_>>();
+        let widened = widen_f16(&samples);
+        for (sample, widened) in samples.iter().zip(widened) {
+            assert_eq!(widened.to_bits(), sample.to_f32().to_bits(), "{:#06x}", sample.to_bits());
+        }
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture` to compare against the scalar loop
+    #[test]
+    #[ignore]
+    fn widen_f16_timing() {
+        let samples = (0..1 << 24).map(|index| f16::from_bits(index as u16)).collect::<Vec<_>>();
+
+        let start = std::time::Instant::now();
+        let scalar = samples.iter().map(|sample| sample.to_f32()).collect::<Vec<_>>();
+        let scalar_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let widened = widen_f16(&samples);
+        let widened_time = start.elapsed();
+
+        assert_eq!(widened.len(), scalar.len());
+        println!("scalar loop {:?}, widen_f16 {:?}", scalar_time, widened_time);
+    }
+
+    #[test]
+    fn luminance_chroma_round_trip() {
+        // Colors that stay the same over each 2x2 block, so that averaging the chroma loses nothing
+        let color = |x: usize, y: usize, num_channels: usize| {
+            let (x, y) = ((x / 2) as f32, (y / 2) as f32);
+            [0.2 + x * 0.05, 0.1 + y * 0.03, 0.5 + (x + y) * 0.02, 1.0 - x * 0.01][..num_channels].to_vec()
+        };
+
+        for num_channels in [3, 4] {
+            let path = temp_path(&format!("luminance_chroma_{}.exr", num_channels));
+            let data = (0..6).flat_map(|y| (0..10).flat_map(move |x| color(x, y, num_channels))).collect::<Vec<f32>>();
+            let status = unsafe {
+                write_texture_luminance_chroma(path.as_ptr(), 10, 6, num_channels as u32, ExrPixelFormat::F32, ExrEncoding::ZIP1, data.as_ptr() as *const c_void)
+            };
+            assert_eq!(status, ExrStatus::Ok);
+
+            // RGB comes back with the opaque alpha filled in, all of it F16 as stored
+            let loaded = load(&path, &ExrLoadOptions { requested_format: ExrPixelFormat::F32, ..Default::default() });
+            assert_eq!((loaded.width, loaded.height, loaded.num_channels, loaded.format), (10, 6, 4, ExrPixelFormat::F32));
+            for (index, (pixel, expected)) in loaded.samples::<f32>().chunks_exact(4).zip(data.chunks_exact(num_channels)).enumerate() {
+                let expected = [expected[0], expected[1], expected[2], expected.get(3).copied().unwrap_or(1.0)];
+                assert!(pixel.iter().zip(expected).all(|(sample, expected)| (sample - expected).abs() < 0.01), "pixel {}: {:?} for {:?}", index, pixel, expected);
+            }
+        }
+
+        // Chroma is stored for pairs of pixels and lines, which an odd size does not split into
+        let path = temp_path("luminance_chroma_odd.exr");
+        let _ = std::fs::remove_file(path.to_str().unwrap());
+        let data = vec![0.5_f32; 9 * 7 * 3];
+        for (width, height) in [(9, 6), (10, 7), (9, 7)] {
+            let status = unsafe {
+                write_texture_luminance_chroma(path.as_ptr(), width, height, 3, ExrPixelFormat::F32, ExrEncoding::ZIP1, data.as_ptr() as *const c_void)
+            };
+            assert_eq!(status, ExrStatus::InvalidArgument, "{}x{}", width, height);
+        }
+        assert!(!Path::new(path.to_str().unwrap()).exists());
+
+        let path = temp_path("luminance_chroma_3.exr");
+        let mut dst = vec![0.0_f32; 10 * 6 * 4];
+        let (mut width, mut height, mut num_channels, mut format) = (0, 0, 0, ExrPixelFormat::Unknown);
+        let status = unsafe {
+            load_into(
+                path.as_ptr(), std::ptr::null(), false, dst.as_mut_ptr() as *mut c_void, dst.len() * 4, 0, &mut width, &mut height,
+                &mut num_channels, &mut format
+            )
+        };
+        assert_eq!(status, ExrStatus::Unsupported);
+        assert_eq!((width, height, num_channels, format), (-1, -1, -1, ExrPixelFormat::Unknown));
+    }
+
+    #[test]
+    fn hdr_non_finite_samples() {
+        let pixels = [[f32::NAN, f32::INFINITY, f32::NEG_INFINITY], [-1.0, 0.5, 2.0], [f32::MAX, -0.0, 1e-40]];
+        // 8 pixels or more are run length encoded, fewer stored flat
+        for width in [3, 12] {
+            let path = temp_path(&format!("non_finite_{}.hdr", width));
+            let data = pixels.iter().cycle().take(width).flatten().copied().collect::<Vec<_>>();
+            assert_eq!(unsafe { write_hdr(path.as_ptr(), width as i32, 1, data.as_ptr()) }, ExrStatus::Ok);
+
+            let loaded = load(&path, &ExrLoadOptions::default());
+            assert_eq!((loaded.width, loaded.height, loaded.num_channels), (width, 1, 3));
+            let samples = loaded.samples::<f32>();
+            assert!(samples.iter().all(|sample| sample.is_finite() && *sample >= 0.0), "{:?}", samples);
+
+            for (sample, expected) in samples.iter().zip(&data) {
+                match *expected {
+                    expected if expected.is_nan() || expected <= 1e-32 => assert_eq!(*sample, 0.0),
+                    expected if expected > 1e38 => assert!(*sample > 1e38),
+                    expected => assert!((sample - expected).abs() <= expected / 64.0, "{} for {}", sample, expected),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partial_file_fills_missing_rows() {
+        let path = temp_path("partial.exr");
+        let data = gradient(16, 8, 4);
+        let options = ExrWriteOptions {
+            encoding: ExrEncoding::Uncompressed, block_mode: BLOCK_MODE_SCAN_LINES, line_order: LINE_ORDER_INCREASING, ..Default::default()
+        };
+        assert_eq!(write(&path, 16, 8, 4, ExrPixelFormat::F32, &options, &data), ExrStatus::Ok);
+
+        // Uncompressed blocks hold a single line, after its y coordinate and byte count. Cutting into the fifth
+        // block from the end loses five rows
+        let block_len = 2 * mem::size_of::<i32>() + 16 * 4 * mem::size_of::<f32>();
+        let file = std::fs::OpenOptions::new().write(true).open(path.to_str().unwrap()).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 4 * block_len as u64 - 100).unwrap();
+        drop(file);
+
+        let default_pixel = [0.25, 0.5, 0.75, 1.0];
+        let (mut width, mut height, mut num_channels, mut format, mut missing_rows) = (0, 0, 0, ExrPixelFormat::Unknown, 0);
+        let mut pixels = std::ptr::null_mut();
+        let status = unsafe {
+            load_partial(
+                path.as_ptr(), std::ptr::null(), &default_pixel, &mut width, &mut height, &mut num_channels, &mut format,
+                &mut missing_rows, &mut pixels
+            )
+        };
+        let loaded = Loaded { width: width as usize, height: height as usize, num_channels: num_channels as usize, format, pixels };
+        assert_eq!(status, ExrStatus::PartialData);
+        assert_eq!((loaded.width, loaded.height, loaded.num_channels, loaded.format), (16, 8, 4, ExrPixelFormat::F32));
+        assert_eq!(missing_rows, 5);
+
+        let row_len = 16 * 4;
+        let samples = loaded.samples::<f32>();
+        assert_eq!(samples[..3 * row_len], data[..3 * row_len]);
+        assert!(samples[3 * row_len..].chunks_exact(4).all(|pixel| pixel == default_pixel));
+    }
+
+    #[test]
+    fn deep_pixels_flatten_to_front_sample() {
+        use exr::meta::attribute::BlockType;
+
+        // exr writes no deep files, so this one is put together by hand: a deep scan line header with F16 colors
+        // and an F32 depth, followed by uncompressed single line blocks. Each pixel lists its samples as (Z, RGBA)
+        type Sample = (f32, [f32; 4]);
+        let rows: [Vec<Vec<Sample>>; 2] = [
+            vec![vec![(5.0, [0.1, 0.1, 0.1, 1.0]), (2.0, [0.5, 0.25, 0.75, 0.5]), (9.0, [1.0, 1.0, 1.0, 1.0])], vec![], vec![(1.0, [0.125, 0.25, 0.375, 1.0]), (3.0, [0.0, 0.0, 0.0, 1.0])]],
+            vec![vec![(4.0, [0.5, 0.5, 0.5, 0.25])], vec![(8.0, [0.0; 4]), (7.0, [0.0; 4]), (6.0, [0.0; 4]), (0.5, [0.75, 0.5, 0.25, 1.0])], vec![(2.0, [1.0, 0.0, 0.0, 1.0]), (2.5, [0.0, 1.0, 0.0, 1.0])]],
+        ];
+        let expected = [[0.5, 0.25, 0.75, 0.5], [0.0; 4], [0.125, 0.25, 0.375, 1.0], [0.5, 0.5, 0.5, 0.25], [0.75, 0.5, 0.25, 1.0], [1.0, 0.0, 0.0, 1.0]];
+
+        let channel = |name: &str, sample_type| ChannelDescription { name: Text::from(name), sample_type, quantize_linearly: false, sampling: Vec2(1, 1) };
+        let channels = ["A", "B", "G", "R"].iter().map(|name| channel(name, SampleType::F16))
+            .chain(std::iter::once(channel("Z", SampleType::F32)))
+            .collect();
+        let channels = ChannelList::new(channels);
+        let bounds = IntegerBounds::new(Vec2(0, 0), Vec2(3, 2));
+        let attributes = [
+            ("channels", AttributeValue::ChannelList(channels)),
+            ("compression", AttributeValue::Compression(Compression::Uncompressed)),
+            ("dataWindow", AttributeValue::IntegerBounds(bounds)),
+            ("displayWindow", AttributeValue::IntegerBounds(bounds)),
+            ("lineOrder", AttributeValue::LineOrder(LineOrder::Increasing)),
+            ("pixelAspectRatio", AttributeValue::F32(1.0)),
+            ("screenWindowCenter", AttributeValue::FloatVec2(Vec2(0.0, 0.0))),
+            ("screenWindowWidth", AttributeValue::F32(1.0)),
+            ("name", AttributeValue::Text(Text::from("deep"))),
+            ("type", AttributeValue::BlockType(BlockType::DeepScanLine)),
+            ("version", AttributeValue::I32(1)),
+            ("maxSamplesPerPixel", AttributeValue::I32(4)),
+        ];
+
+        let mut bytes = Vec::new();
+        exr::meta::magic_number::write(&mut bytes).unwrap();
+        let requirements = Requirements {
+            file_format_version: 2, is_single_layer_and_tiled: false, has_long_names: false, has_deep_data: true, has_multiple_layers: false,
+        };
+        requirements.write(&mut bytes).unwrap();
+        for (name, value) in &attributes {
+            exr::meta::attribute::write(name.as_bytes(), value, &mut bytes).unwrap();
+        }
+        exr::meta::sequence_end::write(&mut bytes).unwrap();
+
+        let blocks = rows.iter().enumerate().map(|(y, row)| {
+            let samples = row.iter().flatten().collect::<Vec<_>>();
+            let mut block = Vec::new();
+            block.extend_from_slice(&(y as i32).to_le_bytes());
+            block.extend_from_slice(&(row.len() as u64 * 4).to_le_bytes());
+            block.extend_from_slice(&(samples.len() as u64 * 12).to_le_bytes());
+            block.extend_from_slice(&(samples.len() as u64 * 12).to_le_bytes());
+            row.iter().scan(0, |count, pixel| { *count += pixel.len() as i32; Some(*count) })
+                .for_each(|count| block.extend_from_slice(&count.to_le_bytes()));
+            for channel in [3, 2, 1, 0] {
+                samples.iter().for_each(|(_, rgba)| block.extend_from_slice(&f16::from_f32(rgba[channel]).to_le_bytes()));
+            }
+            samples.iter().for_each(|(depth, _)| block.extend_from_slice(&depth.to_le_bytes()));
+            block
+        }).collect::<Vec<_>>();
+
+        let mut offset = bytes.len() + blocks.len() * mem::size_of::<u64>();
+        for block in &blocks {
+            bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            offset += block.len();
+        }
+        blocks.iter().for_each(|block| bytes.extend_from_slice(block));
+
+        let path = temp_path("deep.exr");
+        std::fs::write(path.to_str().unwrap(), bytes).unwrap();
+
+        let (mut width, mut height, mut pixels) = (0, 0, std::ptr::null_mut());
+        assert_eq!(unsafe { load_deep_flattened(path.as_ptr(), std::ptr::null(), &mut width, &mut height, &mut pixels) }, ExrStatus::Ok);
+        let loaded = Loaded { width: width as usize, height: height as usize, num_channels: 4, format: ExrPixelFormat::F32, pixels };
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.samples::<f32>(), expected.concat());
+    }
+}